@@ -26,6 +26,6 @@ fn main() {
             cmd.get_name().to_string(),
             outdir.clone(),
         )
-        .expect(&format!("Failed to generate completions for {}", shell));
+        .unwrap_or_else(|_| panic!("Failed to generate completions for {}", shell));
     }
 }
@@ -23,9 +23,188 @@ pub fn build_cli() -> Command<'static> {
                                 .long("no-confirm")
                                 .help("Do not ask for confirmation"),
                         )
+                        .arg(
+                            Arg::new("query")
+                                .short('q')
+                                .long("query")
+                                .help("Only include cards matching this query, such as 'tag:rust due:<3d -archived'")
+                                .takes_value(true)
+                                .allow_hyphen_values(true),
+                        )
+                        .arg(Arg::new("PATH").index(1)),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .alias("l")
+                        .about("List cards along with their scheduling state")
+                        .arg(
+                            Arg::new("query")
+                                .short('q')
+                                .long("query")
+                                .visible_alias("filter")
+                                .help("Only include cards matching this query, such as 'tag:rust due:<3d -archived'")
+                                .takes_value(true)
+                                .allow_hyphen_values(true),
+                        )
+                        .arg(
+                            Arg::new("sort")
+                                .short('s')
+                                .long("sort")
+                                .help("The column to sort cards by")
+                                .takes_value(true)
+                                .possible_values([
+                                    "path",
+                                    "due",
+                                    "interval",
+                                    "reviews",
+                                    "lapses",
+                                    "last-review",
+                                    "avg-time",
+                                ]),
+                        )
+                        .arg(
+                            Arg::new("reverse")
+                                .short('r')
+                                .long("reverse")
+                                .help("Sort in descending order"),
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .short('f')
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(["table", "csv", "json"]),
+                        )
+                        .arg(
+                            Arg::new("archived")
+                                .long("archived")
+                                .help("Only list archived cards"),
+                        )
+                        .arg(Arg::new("PATH").index(1).multiple_values(true)),
+                )
+                .subcommand(
+                    Command::new("leeches")
+                        .about("List cards that keep being forgotten, most lapses first")
+                        .arg(
+                            Arg::new("query")
+                                .short('q')
+                                .long("query")
+                                .visible_alias("filter")
+                                .help("Only include cards matching this query, such as 'tag:rust'")
+                                .takes_value(true)
+                                .allow_hyphen_values(true),
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .short('f')
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(["table", "csv", "json"]),
+                        )
+                        .arg(Arg::new("PATH").index(1).multiple_values(true)),
+                )
+                .subcommand(
+                    Command::new("archive")
+                        .about("Archive the files of the given cards, excluding them from review")
+                        .arg(
+                            Arg::new("query")
+                                .short('q')
+                                .long("query")
+                                .help("Only archive cards matching this query, such as 'tag:rust lapses:>8'")
+                                .takes_value(true)
+                                .allow_hyphen_values(true),
+                        )
+                        .arg(
+                            Arg::new("PATH")
+                                .index(1)
+                                .multiple_values(true)
+                                .required_unless_present("query"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("unarchive")
+                        .about("Unarchive the files of the given cards")
+                        .arg(
+                            Arg::new("query")
+                                .short('q')
+                                .long("query")
+                                .help("Only unarchive cards matching this query, such as 'tag:rust'")
+                                .takes_value(true)
+                                .allow_hyphen_values(true),
+                        )
+                        .arg(
+                            Arg::new("PATH")
+                                .index(1)
+                                .multiple_values(true)
+                                .required_unless_present("query"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("suspend")
+                        .about("Hide the files of the given cards from review until a date")
+                        .arg(
+                            Arg::new("until")
+                                .short('u')
+                                .long("until")
+                                .help("The date to hide cards until, such as 2022-12-31")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("query")
+                                .short('q')
+                                .long("query")
+                                .help("Only suspend cards matching this query, such as 'tag:rust'")
+                                .takes_value(true)
+                                .allow_hyphen_values(true),
+                        )
+                        .arg(
+                            Arg::new("PATH")
+                                .index(1)
+                                .multiple_values(true)
+                                .required_unless_present("query"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("unsuspend")
+                        .about("Lift the suspension of the files of the given cards")
+                        .arg(
+                            Arg::new("query")
+                                .short('q')
+                                .long("query")
+                                .help("Only unsuspend cards matching this query, such as 'suspended'")
+                                .takes_value(true)
+                                .allow_hyphen_values(true),
+                        )
+                        .arg(
+                            Arg::new("PATH")
+                                .index(1)
+                                .multiple_values(true)
+                                .required_unless_present("query"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("assign-ids")
+                        .alias("a")
+                        .about("Give every card that doesn't have one a stable id in its frontmatter")
+                        .arg(Arg::new("PATH").index(1)),
+                )
+                .subcommand(
+                    Command::new("migrate-history")
+                        .alias("m")
+                        .about("Move review history between card frontmatter and .spacedhistory files")
+                        .arg(
+                            Arg::new("to")
+                                .short('t')
+                                .long("to")
+                                .help("The backend to move history into")
+                                .takes_value(true)
+                                .required(true)
+                                .possible_values(["frontmatter", "sidecar"]),
+                        )
                         .arg(Arg::new("PATH").index(1)),
                 ),
-        )       
+        )
         .subcommand(
             Command::new("notes")
                 .alias("n")
@@ -52,15 +231,121 @@ pub fn build_cli() -> Command<'static> {
                         .short('a')
                         .long("algorithm")
                         .takes_value(true)
-                        .possible_values(&[
+                        .possible_values([
                             "all",
                             "leitner",
                             // "half-life" // duolingo
                             // "super-memo"
                         ]),
                 )
-                .arg(Arg::new("no-shuffle").short('S').long("no-shuffle"))
+                .arg(
+                    Arg::new("no-shuffle")
+                        .short('S')
+                        .long("no-shuffle")
+                        .help("Review cards in the order they're found in, same as --order walk"),
+                )
+                .arg(
+                    Arg::new("order")
+                        .short('o')
+                        .long("order")
+                        .help("The order to review cards in; defaults to overdue-first with --max-cards or --time, and random otherwise")
+                        .takes_value(true)
+                        .possible_values([
+                            "random",
+                            "overdue-first",
+                            "folder-grouped-shuffle",
+                            "new-last",
+                            "interleaved-by-deck",
+                            "walk",
+                        ]),
+                )
+                .arg(
+                    Arg::new("type-answer")
+                        .short('t')
+                        .long("type-answer")
+                        .help("Type in answers before revealing them, as if every card had type_answer: true"),
+                )
+                .arg(
+                    Arg::new("query")
+                        .short('q')
+                        .long("query")
+                        .help("Only review cards matching this query, such as 'tag:rust due:<3d -archived'")
+                        .takes_value(true)
+                        .allow_hyphen_values(true),
+                )
+                .arg(
+                    Arg::new("new-per-day")
+                        .short('n')
+                        .long("new-per-day")
+                        .help("The maximum number of new cards to introduce today, overriding new_per_day in the config file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("reviews-per-day")
+                        .short('r')
+                        .long("reviews-per-day")
+                        .help("The maximum number of cards that aren't new to review today, overriding reviews_per_day in the config file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("max-cards")
+                        .short('m')
+                        .long("max-cards")
+                        .help("Review at most this many cards, starting with the most overdue")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("time")
+                        .short('T')
+                        .long("time")
+                        .help("End the session after the first card answered once this much time has passed, such as 15m or 1h30m, starting with the most overdue cards")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .short('g')
+                        .long("tag")
+                        .help("Only review cards with this tag; may be given more than once to review cards with any of the tags")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("exclude-tag")
+                        .short('G')
+                        .long("exclude-tag")
+                        .help("Don't review cards with this tag; may be given more than once")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("summary")
+                        .long("summary")
+                        .help("Print a summary of the reviews done today instead of reviewing, such as for logging"),
+                )
+                .arg(
+                    Arg::new("summary-format")
+                        .long("summary-format")
+                        .help("The format of the summary printed at the end of the session or with --summary")
+                        .takes_value(true)
+                        .possible_values(["text", "json"]),
+                )
                 .arg(Arg::new("PATH").index(1)),
+        )
+        .subcommand(
+            Command::new("undo")
+                .alias("u")
+                .about("Undo the last review events, including those from previous sessions")
+                .arg(
+                    Arg::new("no-confirm")
+                        .short('y')
+                        .long("no-confirm")
+                        .help("Do not ask for confirmation"),
+                )
+                .arg(
+                    Arg::new("COUNT")
+                        .index(1)
+                        .help("The number of events to undo, which defaults to 1"),
+                ),
         );
     #[cfg(feature = "import")]
     {
@@ -75,7 +360,13 @@ pub fn build_cli() -> Command<'static> {
                         .help("The format of the file to import")
                         .takes_value(true)
                         .required(true)
-                        .possible_values(&["mochi", "anki"]),
+                        .possible_values(["mochi", "anki"]),
+                )
+                .arg(
+                    Arg::new("single-file")
+                        .short('s')
+                        .long("single-file")
+                        .help("Write each deck to a single file with one card per heading instead of one file per card"),
                 )
                 .arg(Arg::new("PATH").index(1).required(true))
                 .arg(Arg::new("OUT_DIR").index(2).required(true)),
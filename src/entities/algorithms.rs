@@ -1,29 +1,47 @@
 use chrono::{Date, Duration, Utc};
 
-pub fn leitner(review_history: &mut Vec<(Date<Utc>, bool)>) -> bool {
-    let last_review = match review_history.pop() {
-        Some(l) => l,
-        None => return true,
-    };
+use crate::entities::history::Review;
+
+/// How long an answer can take before remembering the card counts as a hesitant recall, which
+/// grows its interval less.
+pub const SLOW_ANSWER_MS: u64 = 30_000;
+
+/// Returns the date the card with `review_history` is next due under `algorithm`. Cards that
+/// haven't been reviewed yet are due today.
+pub fn due_date(algorithm: &str, review_history: &[Review]) -> Date<Utc> {
+    match algorithm {
+        "all" => Utc::today(),
+        "leitner" => leitner_due_date(review_history),
+        _ => panic!(), // Cannot occur because clap will block invalid algorithm arguments
+    }
+}
 
-    review_history.push(last_review);
+fn leitner_due_date(review_history: &[Review]) -> Date<Utc> {
+    let last_review = match review_history.last() {
+        Some(l) => *l,
+        None => return Utc::today(),
+    };
 
-    if last_review.1 {
+    if last_review.remembered {
         let mut spacing = 0.5_f64;
         let mut prev_date = chrono::MIN_DATE;
         let mut failure_registered_today = false;
 
         for event in review_history {
-            if event.0 == prev_date {
-                if !failure_registered_today && !event.1 {
+            if event.date == prev_date {
+                if !failure_registered_today && !event.remembered {
                     spacing *= 0.25;
                     failure_registered_today = true;
                 }
             } else {
-                prev_date = event.0;
+                prev_date = event.date;
 
-                if event.1 {
-                    spacing *= 2.0;
+                if event.remembered {
+                    // cards that took a while to recall are moved up less
+                    spacing *= match event.time_ms {
+                        Some(time_ms) if time_ms > SLOW_ANSWER_MS => 1.5,
+                        _ => 2.0,
+                    };
                     failure_registered_today = false;
                 } else {
                     spacing *= 0.5;
@@ -32,8 +50,8 @@ pub fn leitner(review_history: &mut Vec<(Date<Utc>, bool)>) -> bool {
             }
         }
 
-        last_review.0 + Duration::days((spacing.round() as i64).max(1)) <= Utc::today()
+        last_review.date + Duration::days((spacing.round() as i64).max(1))
     } else {
-        true
+        last_review.date
     }
 }
@@ -1,29 +1,413 @@
-use crate::entities::{algorithms, frontmatter};
-use chrono::format::ParseError;
-use chrono::{Date, NaiveDate, Utc};
-use serde_yaml::Mapping;
-use serde_yaml::Value;
+use crate::entities::history::{self, Backend, Review, ReviewHistoryError};
+use crate::entities::{algorithms, choices, cloze, config, frontmatter};
+use crate::error::ValueError;
+use chrono::{Date, Duration, NaiveDate, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::error::Error;
 use std::ffi::OsStr;
 use std::fmt;
-use std::path::{Component, Path};
-use walkdir::{DirEntry, WalkDir};
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
+use walkdir::WalkDir;
 
-#[derive(Debug)]
-pub enum ReviewHistoryError {
-    DateParseError(ParseError),
-    ValueError,
+/// A single reviewable card.
+///
+/// Most files hold exactly one card, but files with `format: headings` in their frontmatter hold
+/// one card per `## ` heading, which are told apart by their anchor. Cards containing cloze
+/// deletions are further split into one card per cloze number, and files with `reverse: true`
+/// also yield a reversed copy of each of their cards.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Card {
+    path: PathBuf,
+    anchor: Option<String>,
+    cloze: Option<u32>,
+    reversed: bool,
 }
 
-impl fmt::Display for ReviewHistoryError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Card {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn anchor(&self) -> Option<&str> {
+        self.anchor.as_deref()
+    }
+
+    /// Returns the name that this card's history is kept under within its file, or `None` if it is
+    /// the only card in its file.
+    pub fn key(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(anchor) = &self.anchor {
+            parts.push(anchor.clone());
+        }
+        if let Some(cloze) = self.cloze {
+            parts.push(format!("c{}", cloze));
+        }
+        if self.reversed {
+            parts.push(String::from("reverse"));
+        }
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(":"))
+        }
+    }
+
+    pub fn components(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let body = frontmatter::read_body(&self.path)?;
+        match &self.anchor {
+            None => Ok(body.split("\n---\n").map(|s| s.to_string()).collect()),
+            Some(anchor) => parse_sections(&body)
+                .into_iter()
+                .find(|section| &section.anchor == anchor)
+                .map(|section| section.components)
+                .ok_or_else(|| {
+                    Box::new(ValueError::from(format!("anchor {} not found", self)))
+                        as Box<dyn Error>
+                }),
+        }
+    }
+
+    /// Returns what should be shown for each step of revealing the card: every face shows one more
+    /// component than the last, and for cloze cards the first face has the active cloze blanked
+    /// out while the following ones reveal it. Reversed cards show their components last to first.
+    /// The options of multiple choice cards are left out, since they are shown separately.
+    pub fn faces(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut components = self.components()?;
+        if self.reversed {
+            components.reverse();
+        }
+        let multiple_choice = self.multiple_choice()?;
+        let mut faces = (0..components.len())
+            .map(|i| {
+                let face = components[..i + 1].join("\n---\n");
+                if multiple_choice {
+                    choices::strip(&face)
+                } else {
+                    face
+                }
+            })
+            .collect::<Vec<String>>();
+        if let Some(active) = self.cloze {
+            if faces.len() == 1 {
+                faces.push(faces[0].clone());
+            }
+            for (i, face) in faces.iter_mut().enumerate() {
+                *face = cloze::render(face, active, i > 0);
+            }
+        }
+        Ok(faces)
+    }
+
+    /// Returns the text the reviewer is expected to come up with: the hidden cloze for cloze
+    /// cards, and the last component shown otherwise.
+    pub fn answer(&self) -> Result<String, Box<dyn Error>> {
+        let mut components = self.components()?;
+        if let Some(active) = self.cloze {
+            return Ok(cloze::answers(&components.join("\n---\n"), active).join(", "));
+        }
+        if self.reversed {
+            components.reverse();
+        }
+        Ok(components.pop().unwrap_or_default().trim().to_string())
+    }
+
+    /// Returns the options of a multiple choice card, written as a markdown task list with the
+    /// correct ones checked off, along with whether each of them is correct. Cards that aren't
+    /// multiple choice give an empty list.
+    pub fn choices(&self) -> Result<Vec<(String, bool)>, Box<dyn Error>> {
+        if !self.multiple_choice()? {
+            return Ok(Vec::new());
+        }
+        Ok(choices::parse(&self.components()?.join("\n---\n")))
+    }
+
+    /// Whether the task list in this card holds the options of a multiple choice question, as
+    /// requested by `choices: true` in its frontmatter. Task lists are left as they are otherwise.
+    fn multiple_choice(&self) -> Result<bool, Box<dyn Error>> {
+        read_bool(&frontmatter::read_fm(&self.path)?, "choices", &self.path)
+    }
+
+    /// Whether the answer to this card should be typed in before it is revealed, as requested by
+    /// `type_answer: true` in its frontmatter.
+    pub fn type_answer(&self) -> Result<bool, Box<dyn Error>> {
+        read_bool(
+            &frontmatter::read_fm(&self.path)?,
+            "type_answer",
+            &self.path,
+        )
+    }
+
+    /// Returns the tags listed under `tags` in this card's frontmatter, along with those inherited
+    /// from the `.spaced.yml` configs of the directories it's in. Cards that share their file also
+    /// get the tags under their own `subcards.<key>.tags`, such as when they're leeches.
+    pub fn tags(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut tags = Vec::new();
+        for (config_path, config) in config::dir_configs(&self.path)? {
+            tags.extend(read_strings(&config, "tags", &config_path)?);
+        }
+        let frontmatter = frontmatter::read_fm(&self.path)?;
+        tags.extend(read_strings(&frontmatter, "tags", &self.path)?);
+        if self.key().is_some() {
+            if let Some(entry) = history::card_entry(&frontmatter, self) {
+                tags.extend(read_strings(entry, "tags", &self.path)?);
+            }
+        }
+        tags.sort();
+        tags.dedup();
+        Ok(tags)
+    }
+
+    /// Returns the group of cards that shouldn't be reviewed on the same day as this one.
+    pub fn sibling_group(&self) -> Result<SiblingGroup, Box<dyn Error>> {
+        match config::dir_setting(&self.path, "siblings")? {
+            Some((config_path, Value::Bool(true))) => Ok(SiblingGroup::Dir(
+                config_path.parent().unwrap().to_path_buf(), // Can be unwrapped safely because config paths are file paths
+            )),
+            Some((_, Value::Bool(false))) | None => {
+                Ok(SiblingGroup::Text(self.path.clone(), self.anchor.clone()))
+            }
+            Some((config_path, _)) => Err(Box::new(ValueError::from(format!(
+                "`siblings` key was not of type bool in {}",
+                config_path.display()
+            )))),
+        }
+    }
+}
+
+/// A set of cards that are related closely enough that reviewing one of them gives the others
+/// away, so only one of them should be reviewed each day.
+///
+/// The cards generated from the same text, such as its clozes and its reversed copy, are always
+/// siblings, and all the cards within a directory become siblings when its `.spaced.yml` contains
+/// `siblings: true`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum SiblingGroup {
+    /// The cards of the given file with the given anchor.
+    Text(PathBuf, Option<String>),
+    /// The cards within the given directory.
+    Dir(PathBuf),
+}
+
+impl SiblingGroup {
+    pub fn members(&self) -> Result<Vec<Card>, Box<dyn Error>> {
         match self {
-            ReviewHistoryError::DateParseError(e) => e.fmt(f),
-            ReviewHistoryError::ValueError => write!(f, "ValueError"), // TODO: Determine how this should be formatted
+            SiblingGroup::Text(path, anchor) => Ok(file_cards(path)?
+                .into_iter()
+                .filter(|card| card.anchor() == anchor.as_deref())
+                .collect()),
+            SiblingGroup::Dir(dir) => Ok(get_cards(&dir.to_string_lossy(), "all")),
+        }
+    }
+}
+
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.key() {
+            Some(key) => write!(f, "{}#{}", self.path.display(), key),
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+struct Section {
+    anchor: String,
+    components: Vec<String>,
+}
+
+/// Splits the body of a `format: headings` file into one section per `## ` heading.
+///
+/// The heading is the question and the rest of the section the answer, unless the section
+/// contains its own `---` separators, in which case those decide where components end. Anchors
+/// come from a trailing `{#anchor}` on the heading if there is one and are derived from the
+/// heading text otherwise, so explicit anchors should be used for headings that may be reworded.
+fn parse_sections(body: &str) -> Vec<Section> {
+    lazy_static! {
+        static ref ANCHOR: Regex = Regex::new(r"\s*\{#([^}\s]+)\}\s*$").unwrap();
+    }
+
+    let mut headings: Vec<(&str, Vec<&str>)> = Vec::new();
+    let mut in_fence = false;
+    for line in body.split('\n') {
+        if line.starts_with("```") {
+            in_fence = !in_fence;
+        }
+        match line.strip_prefix("## ") {
+            Some(heading) if !in_fence => headings.push((heading, vec![])),
+            _ => {
+                if let Some((_, lines)) = headings.last_mut() {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+
+    let mut seen = HashMap::new();
+    headings
+        .into_iter()
+        .map(|(heading, lines)| {
+            let (heading, anchor) = match ANCHOR.captures(heading) {
+                Some(captures) => (
+                    &heading[..captures.get(0).unwrap().start()],
+                    captures[1].to_string(),
+                ),
+                None => (heading, slugify(heading)),
+            };
+            let count = seen.entry(anchor.clone()).or_insert(0);
+            let anchor = match *count {
+                0 => anchor,
+                n => format!("{}-{}", anchor, n),
+            };
+            *count += 1;
+
+            let content = lines.join("\n").trim_matches('\n').to_string();
+            let text = format!("## {}\n\n{}\n", heading, content);
+            let components = if text.contains("\n---\n") {
+                text.split("\n---\n").map(|s| s.to_string()).collect()
+            } else {
+                vec![format!("## {}\n", heading), content + "\n"]
+            };
+            Section { anchor, components }
+        })
+        .collect()
+}
+
+fn slugify(heading: &str) -> String {
+    heading
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            c if c.is_alphanumeric() || c == '_' => Some(c),
+            ' ' | '-' => Some('-'),
+            _ => None,
+        })
+        .collect()
+}
+
+fn read_bool(frontmatter: &Mapping, key: &str, path: &Path) -> Result<bool, Box<dyn Error>> {
+    match frontmatter.get(&Value::String(key.to_string())) {
+        Some(Value::Bool(b)) => Ok(*b),
+        None | Some(Value::Null) => Ok(false),
+        Some(_) => Err(Box::new(ValueError::from(format!(
+            "`{}` key was not of type bool in {}",
+            key,
+            path.display()
+        )))),
+    }
+}
+
+fn read_date(
+    frontmatter: &Mapping,
+    key: &str,
+    path: &Path,
+) -> Result<Option<Date<Utc>>, Box<dyn Error>> {
+    let invalid = || {
+        Box::new(ValueError::from(format!(
+            "`{}` key was not a date in {}",
+            key,
+            path.display()
+        )))
+    };
+    match frontmatter.get(&Value::String(key.to_string())) {
+        Some(Value::String(s)) => Ok(Some(Date::from_utc(
+            NaiveDate::parse_from_str(s, history::DATE_FORMAT).map_err(|_| invalid())?,
+            Utc,
+        ))),
+        None | Some(Value::Null) => Ok(None),
+        Some(_) => Err(invalid()),
+    }
+}
+
+fn read_strings(mapping: &Mapping, key: &str, path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    match mapping.get(&Value::String(key.to_string())) {
+        Some(Value::Sequence(values)) => values
+            .iter()
+            .map(|value| match value {
+                Value::String(s) => Ok(s.clone()),
+                _ => Err(Box::new(ValueError::from(format!(
+                    "`{}` key contained a value that was not of type string in {}",
+                    key,
+                    path.display()
+                ))) as Box<dyn Error>),
+            })
+            .collect(),
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(_) => Err(Box::new(ValueError::from(format!(
+            "`{}` key was not of type sequence in {}",
+            key,
+            path.display()
+        )))),
+    }
+}
+
+/// Returns the cards held by the file at `path`.
+pub fn file_cards(path: &Path) -> Result<Vec<Card>, Box<dyn Error>> {
+    let (frontmatter, body) = frontmatter::read_fm_and_body(path)?;
+    let reverse = read_bool(&frontmatter, "reverse", path)?;
+    let texts = match frontmatter.get(&Value::String(String::from("format"))) {
+        Some(Value::String(format)) if format == "headings" => parse_sections(&body)
+            .into_iter()
+            .map(|section| (Some(section.anchor), section.components.join("\n---\n")))
+            .collect(),
+        None | Some(Value::Null) => vec![(None, body)],
+        Some(_) => {
+            return Err(Box::new(ValueError::from(format!(
+                "unsupported `format` in {}",
+                path.display()
+            ))))
+        }
+    };
+
+    let mut cards = Vec::new();
+    for (anchor, text) in texts {
+        let clozes = cloze::numbers(&text);
+        if clozes.is_empty() {
+            cards.push(Card {
+                path: path.to_path_buf(),
+                anchor: anchor.clone(),
+                cloze: None,
+                reversed: false,
+            });
+            if reverse {
+                cards.push(Card {
+                    path: path.to_path_buf(),
+                    anchor,
+                    cloze: None,
+                    reversed: true,
+                });
+            }
+        } else {
+            // Clozes already test each part of the card on its own, so they aren't reversed
+            for cloze in clozes {
+                cards.push(Card {
+                    path: path.to_path_buf(),
+                    anchor: anchor.clone(),
+                    cloze: Some(cloze),
+                    reversed: false,
+                });
+            }
         }
     }
+    Ok(cards)
 }
 
-pub fn get_cards(path: &str, algorithm: &str) -> Vec<DirEntry> {
+/// Returns the root of the collection that `path` belongs to, which is the outermost `cards`
+/// directory containing it, or `path` itself if it isn't within one, since it then holds the
+/// `cards` directories instead.
+pub fn collection_root(path: &Path) -> PathBuf {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    path.ancestors()
+        .filter(|dir| dir.file_name() == Some(OsStr::new("cards")))
+        .last()
+        .map(Path::to_path_buf)
+        .unwrap_or(path)
+}
+
+pub fn get_cards(path: &str, algorithm: &str) -> Vec<Card> {
     // TODO: Handle errors here
     WalkDir::new(path)
         .into_iter()
@@ -36,10 +420,9 @@ pub fn get_cards(path: &str, algorithm: &str) -> Vec<DirEntry> {
                 Ok(cp) => cp,
                 Err(_) => return None,
             };
-            if canonical_path
+            if !canonical_path
                 .components()
-                .find(|c| c == &Component::Normal(OsStr::new("cards")))
-                .is_none()
+                .any(|c| c == Component::Normal(OsStr::new("cards")))
             {
                 return None;
             }
@@ -47,157 +430,401 @@ pub fn get_cards(path: &str, algorithm: &str) -> Vec<DirEntry> {
                 Some(e) => e,
                 None => return None,
             };
-            if extension == "md" && review_time(entry.path(), algorithm) {
+            if extension == "md" {
                 Some(entry)
             } else {
                 None
             }
         })
-        .into_iter()
-        .collect::<Vec<DirEntry>>()
+        .flat_map(|entry| {
+            let cards = file_cards(entry.path()).and_then(|cards| {
+                let mut due = Vec::new();
+                for card in cards {
+                    if review_time(&card, algorithm)? {
+                        due.push(card);
+                    }
+                }
+                Ok(due)
+            });
+            match cards {
+                Ok(cards) => cards,
+                // one malformed file shouldn't keep the rest of the cards from being found
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", entry.path().display(), e);
+                    Vec::new()
+                }
+            }
+        })
+        .collect::<Vec<Card>>()
 }
 
-fn review_time(path: &Path, algorithm: &str) -> bool {
+/// Returns whether `card` is due for review under `algorithm`, where every card is due under
+/// `all`. Its suspension and history are read either way, so that cards which can't be read are
+/// always caught here.
+fn review_time(card: &Card, algorithm: &str) -> Result<bool, Box<dyn Error>> {
+    let frontmatter = frontmatter::read_fm(card.path())?;
+    let suspended_until = suspended_until(&frontmatter, card)?;
+    let review_history = read_review_history(card)?;
     if algorithm == "all" {
-        return true;
+        return Ok(true);
     }
-    // TODO: Catch errors here
-    let frontmatter = frontmatter::read_fm(path).unwrap();
     if let Some(archived) = frontmatter.get(&Value::String(String::from("archived"))) {
         // TODO: should I throw an error if archived is not a bool
         if archived.as_bool().unwrap_or(false) {
-            return false;
-        }
-    }
-    let mut review_history = read_review_history(frontmatter).unwrap();
-    match algorithm {
-        "leitner" => algorithms::leitner(&mut review_history),
-        _ => panic!(), // Cannot occur because clap will block invalid algorithm arguments
-    }
-}
-
-fn read_review_history(frontmatter: Mapping) -> Result<Vec<(Date<Utc>, bool)>, ReviewHistoryError> {
-    match frontmatter
-        .get(&Value::String(String::from("reviews")))
-        .unwrap_or(&Value::Sequence(vec![]))
-    {
-        Value::Sequence(sequence) => {
-            let mut review_history = Vec::new();
-            for map in sequence {
-                let date = match map.get("date") {
-                    Some(value) => match value {
-                        Value::String(string) => Date::from_utc(
-                            match NaiveDate::parse_from_str(string, "%Y-%m-%d") {
-                                Ok(d) => d,
-                                Err(e) => return Err(ReviewHistoryError::DateParseError(e)),
-                            },
-                            Utc,
-                        ),
-                        _ => return Err(ReviewHistoryError::ValueError),
-                    },
-                    None => return Err(ReviewHistoryError::ValueError),
-                };
-                let remembered = match map.get("remembered") {
-                    Some(value) => match value {
-                        Value::Bool(b) => *b,
-                        _ => return Err(ReviewHistoryError::ValueError),
-                    },
-                    None => return Err(ReviewHistoryError::ValueError),
-                };
-                review_history.push((date, remembered));
-            }
-            Ok(review_history)
+            return Ok(false);
+        }
+    }
+    if let Some(until) = suspended_until {
+        if until > Utc::today() {
+            return Ok(false);
         }
-        Value::Null => Ok(vec![]),
-        _ => return Err(ReviewHistoryError::ValueError),
     }
+    Ok(algorithms::due_date(algorithm, &review_history) <= Utc::today())
 }
 
-pub fn mark(path: &Path, remembered: bool) {
-    let (mut mapping, body) = match frontmatter::read_fm_and_body(path) {
-        Ok(fm) => fm,
-        Err(e) => panic!("{}", e),
-    };
-
-    match mapping.get_mut(&Value::String(String::from("reviews"))) {
-        Some(reviews) => match reviews {
-            Value::Sequence(s) => {
-                s.push(get_review_item(remembered));
-                frontmatter::write_fm_and_body(path, Value::Mapping(mapping), body).unwrap();
-            }
-            Value::Null => {
-                mapping.insert(
-                    Value::String(String::from("reviews")),
-                    get_review_item(remembered),
-                );
-                frontmatter::write_fm_and_body(path, Value::Mapping(mapping), body).unwrap();
+/// Removes the cards from `cards` that have a sibling which was already reviewed today.
+pub fn bury_reviewed_siblings(cards: Vec<Card>) -> Result<Vec<Card>, Box<dyn Error>> {
+    let today = Utc::today();
+    let mut reviewed_today = HashMap::new();
+    let mut unburied = Vec::new();
+    for card in cards {
+        let group = card.sibling_group()?;
+        if !reviewed_today.contains_key(&group) {
+            let mut reviewed = Vec::new();
+            for member in group.members()? {
+                if read_review_history(&member)?
+                    .last()
+                    .map(|review| review.date == today)
+                    .unwrap_or(false)
+                {
+                    reviewed.push(member);
+                }
             }
-            _ => panic!("Unsupported frontmatter contents in {}", path.display()),
-        },
-        None => {
-            mapping.insert(
-                Value::String(String::from("reviews")),
-                Value::Sequence(vec![get_review_item(remembered)]),
-            );
-            frontmatter::write_fm_and_body(path, Value::Mapping(mapping), body).unwrap();
+            reviewed_today.insert(group.clone(), reviewed);
+        }
+        // members of directory groups are found through the canonical path of the directory, so
+        // they are compared by their canonical paths too
+        let is_card = |member: &Card| {
+            member.key() == card.key()
+                && member.path().canonicalize().ok() == card.path().canonicalize().ok()
+        };
+        if reviewed_today[&group].iter().all(is_card) {
+            unburied.push(card);
         }
     }
+    Ok(unburied)
 }
 
-pub fn unmark(path: &Path) {
-    let (mut mapping, body) = match frontmatter::read_fm_and_body(path) {
-        Ok(fm) => fm,
-        Err(e) => panic!("{}", e),
-    };
+/// The scheduling state of a card, as computed from its frontmatter and review history.
+pub struct CardState {
+    pub due: Date<Utc>,
+    /// The number of days between the last review and the due date.
+    pub interval: i64,
+    pub reviews: usize,
+    /// The number of days on which the card was forgotten, so that forgetting it again during the
+    /// learning steps of a session doesn't count twice.
+    pub lapses: usize,
+    pub last_review: Option<Date<Utc>>,
+    /// The average time taken to answer the card, out of the reviews that were timed.
+    pub average_time_ms: Option<u64>,
+    pub archived: bool,
+    /// The date until which the card is hidden from review, if it has been suspended or buried.
+    pub suspended_until: Option<Date<Utc>>,
+    pub tags: Vec<String>,
+}
 
-    match mapping.get_mut(&Value::String(String::from("reviews"))) {
-        Some(reviews) => match reviews {
-            Value::Sequence(s) => {
-                s.pop();
-                frontmatter::write_fm_and_body(path, Value::Mapping(mapping), body).unwrap();
-            }
-            Value::Null => panic!("Card has already been unmarked {}", path.display()),
-            _ => panic!("Unsupported frontmatter contents in {}", path.display()),
+/// Computes the state of `card`, scheduling it according to `algorithm`.
+pub fn read_state(card: &Card, algorithm: &str) -> Result<CardState, Box<dyn Error>> {
+    let review_history = read_review_history(card)?;
+    let due = algorithms::due_date(algorithm, &review_history);
+    let last_review = review_history.last().map(|review| review.date);
+    let times = review_history
+        .iter()
+        .filter_map(|review| review.time_ms)
+        .collect::<Vec<u64>>();
+    let mut lapse_days = review_history
+        .iter()
+        .filter(|review| !review.remembered)
+        .map(|review| review.date)
+        .collect::<Vec<Date<Utc>>>();
+    lapse_days.dedup();
+    let frontmatter = frontmatter::read_fm(card.path())?;
+    Ok(CardState {
+        due,
+        interval: last_review
+            .map(|date| (due - date).num_days().max(0))
+            .unwrap_or(0),
+        reviews: review_history.len(),
+        lapses: lapse_days.len(),
+        last_review,
+        average_time_ms: if times.is_empty() {
+            None
+        } else {
+            Some(times.iter().sum::<u64>() / times.len() as u64)
         },
-        None => {
-            panic!("Card has already been unmarked {}", path.display())
-        }
+        archived: read_bool(&frontmatter, "archived", card.path())?,
+        suspended_until: suspended_until(&frontmatter, card)?,
+        tags: card.tags()?,
+    })
+}
+
+/// Returns the reviews of `card`, leaving out the times it was peeked at.
+pub fn read_review_history(card: &Card) -> Result<Vec<Review>, ReviewHistoryError> {
+    Ok(Backend::for_card(card.path())
+        .read(card)?
+        .into_iter()
+        .filter(|review| !review.peeked)
+        .collect())
+}
+
+/// Records a review of `card` today, along with how long it took to answer if that's known.
+pub fn mark(card: &Card, remembered: bool, time_ms: Option<u64>) -> Result<(), Box<dyn Error>> {
+    assign_id(card.path())?;
+    Backend::for_card(card.path()).push(
+        card,
+        &Review {
+            date: Utc::today(),
+            remembered,
+            time_ms,
+            peeked: false,
+        },
+    )
+}
+
+/// Records that the answer of `card` was shown today without it being graded.
+pub fn mark_peeked(card: &Card, time_ms: Option<u64>) -> Result<(), Box<dyn Error>> {
+    assign_id(card.path())?;
+    Backend::for_card(card.path()).push(
+        card,
+        &Review {
+            date: Utc::today(),
+            remembered: false,
+            time_ms,
+            peeked: true,
+        },
+    )
+}
+
+pub fn unmark(card: &Card) -> Result<(), Box<dyn Error>> {
+    if Backend::for_card(card.path()).pop(card)? {
+        Ok(())
+    } else {
+        panic!("Card has already been unmarked {}", card)
+    }
+}
+
+pub fn clear_review_history(card: &Card) -> Result<(), Box<dyn Error>> {
+    Backend::for_card(card.path()).clear(card)
+}
+
+pub fn read_id(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    match frontmatter::read_fm(path)?.get(&Value::String(String::from("id"))) {
+        Some(Value::String(id)) => Ok(Some(id.clone())),
+        Some(Value::Null) | None => Ok(None),
+        _ => Err(Box::new(ValueError::from(format!(
+            "`id` key was not of type string in {}",
+            path.display()
+        )))),
     }
 }
 
-pub fn mark_archived(path: &Path, archived: bool) {
-    let (mut mapping, body) = match frontmatter::read_fm_and_body(path) {
-        Ok(fm) => fm,
-        Err(e) => panic!("{}", e),
+/// Returns the id of the card at `path`, giving it a new random one first if it doesn't have one
+/// yet. The second value is true when a new id was assigned.
+pub fn assign_id(path: &Path) -> Result<(String, bool), Box<dyn Error>> {
+    if let Some(id) = read_id(path)? {
+        return Ok((id, false));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let (mut mapping, body) = frontmatter::read_fm_and_body(path)?;
+    mapping.insert(Value::String(String::from("id")), Value::String(id.clone()));
+    frontmatter::write_fm_and_body(path, Value::Mapping(mapping), body)?;
+    if Backend::for_card(path) == Backend::Sidecar {
+        history::stamp_sidecar_id(path, &id)?;
+    }
+    Ok((id, true))
+}
+
+/// Archives or unarchives the file at `path`, which excludes all of its cards from review.
+pub fn mark_archived(path: &Path, archived: bool) -> Result<(), Box<dyn Error>> {
+    let (mut mapping, body) = frontmatter::read_fm_and_body(path)?;
+    let key = Value::String(String::from("archived"));
+    if archived {
+        mapping.insert(key, Value::Bool(true));
+    } else {
+        mapping.remove(&key);
+    }
+    frontmatter::write_mapping_and_body(path, mapping, body)
+}
+
+/// Returns the date until which `card` is hidden from review, which is the later of the date its
+/// file is suspended until and the date the card itself is suspended or buried until.
+fn suspended_until(
+    frontmatter: &Mapping,
+    card: &Card,
+) -> Result<Option<Date<Utc>>, Box<dyn Error>> {
+    let file = read_date(frontmatter, "suspended_until", card.path())?;
+    let own = match history::card_entry(frontmatter, card) {
+        Some(entry) => read_date(entry, "suspended_until", card.path())?,
+        None => None,
     };
+    Ok(file.max(own))
+}
 
-    match mapping.get_mut(&Value::String(String::from("archived"))) {
-        Some(reviews) => match reviews {
-            Value::Bool(b) => {
-                *b = archived;
-            }
-            _ => panic!("Unsupported frontmatter contents in {}", path.display()),
-        },
-        None => {
+/// Returns the date until which `card` itself is suspended, leaving out the suspension of the rest
+/// of its file. For cards that are alone in their file, the two are the same.
+pub fn read_card_suspended_until(card: &Card) -> Result<Option<Date<Utc>>, Box<dyn Error>> {
+    let frontmatter = frontmatter::read_fm(card.path())?;
+    match history::card_entry(&frontmatter, card) {
+        Some(entry) => read_date(entry, "suspended_until", card.path()),
+        None => Ok(None),
+    }
+}
+
+/// Suspends all the cards in the file at `path` until the given date, or lifts the suspension if
+/// it's `None`, including that of cards that were suspended on their own. Unlike archiving,
+/// suspension ends on its own, and it doesn't affect the history of the cards.
+pub fn mark_suspended(path: &Path, until: Option<Date<Utc>>) -> Result<(), Box<dyn Error>> {
+    let (mut mapping, body) = frontmatter::read_fm_and_body(path)?;
+    let key = Value::String(String::from("suspended_until"));
+    match until {
+        Some(until) => {
             mapping.insert(
-                Value::String(String::from("archived")),
-                Value::Bool(archived),
+                key,
+                Value::String(until.format(history::DATE_FORMAT).to_string()),
             );
-            frontmatter::write_fm_and_body(path, Value::Mapping(mapping), body).unwrap();
+        }
+        None => {
+            mapping.remove(&key);
+            if let Some(Value::Mapping(subcards)) =
+                mapping.get_mut(&Value::String(String::from("subcards")))
+            {
+                for (_, subcard) in subcards.iter_mut() {
+                    if let Value::Mapping(subcard) = subcard {
+                        subcard.remove(&key);
+                    }
+                }
+            }
+            history::prune_subcards(&mut mapping);
         }
     }
+    frontmatter::write_mapping_and_body(path, mapping, body)
 }
 
-fn get_review_item(remembered: bool) -> serde_yaml::Value {
-    let mut mapping = serde_yaml::Mapping::new();
-    let today = Utc::today().format("%Y-%m-%d").to_string();
-    mapping.insert(
-        Value::String(String::from("date")),
-        serde_yaml::Value::String(today),
-    );
-    mapping.insert(
-        Value::String(String::from("remembered")),
-        serde_yaml::Value::Bool(remembered),
+/// Hides `card` alone from review until the given date, or lifts that if it's `None`, which is
+/// how cards are buried until the next day without hiding the other cards in their file.
+pub fn mark_card_suspended(card: &Card, until: Option<Date<Utc>>) -> Result<(), Box<dyn Error>> {
+    let (mut mapping, body) = frontmatter::read_fm_and_body(card.path())?;
+    let key = Value::String(String::from("suspended_until"));
+    let entry = history::card_mapping(&mut mapping, card)?;
+    match until {
+        Some(until) => {
+            entry.insert(
+                key,
+                Value::String(until.format(history::DATE_FORMAT).to_string()),
+            );
+        }
+        None => {
+            entry.remove(&key);
+        }
+    }
+    history::prune_subcards(&mut mapping);
+    frontmatter::write_mapping_and_body(card.path(), mapping, body)
+}
+
+/// The tag given to cards that keep being forgotten.
+pub const LEECH_TAG: &str = "leech";
+const DEFAULT_LEECH_THRESHOLD: u64 = 8;
+
+/// What `mark_leech` did to a card.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LeechAction {
+    None,
+    Tagged,
+    /// The card was tagged and its file suspended, as requested by `leech_suspend_days`.
+    Suspended,
+}
+
+/// Returns the number of lapses after which the card at `path` is considered a leech, which can
+/// be set with `leech_threshold` in a directory's `.spaced.yml`.
+pub fn leech_threshold(path: &Path) -> Result<usize, Box<dyn Error>> {
+    match config::dir_setting(path, "leech_threshold")? {
+        Some((_, Value::Number(n))) if n.as_u64().is_some() => Ok(n.as_u64().unwrap() as usize),
+        Some((config_path, _)) => Err(Box::new(ValueError::from(format!(
+            "`leech_threshold` key was not a positive integer in {}",
+            config_path.display()
+        )))),
+        None => Ok(DEFAULT_LEECH_THRESHOLD as usize),
+    }
+}
+
+/// Tags `card` as a leech once it has been forgotten at least as many times as its leech
+/// threshold, also suspending it for the number of days given by `leech_suspend_days` in a
+/// directory's `.spaced.yml`, if any. Cards that are already tagged are left alone, and so are
+/// the other cards in the same file.
+pub fn mark_leech(card: &Card) -> Result<LeechAction, Box<dyn Error>> {
+    let state = read_state(card, "all")?;
+    if state.lapses < leech_threshold(card.path())? || state.tags.iter().any(|t| t == LEECH_TAG) {
+        return Ok(LeechAction::None);
+    }
+    let suspend_days = match config::dir_setting(card.path(), "leech_suspend_days")? {
+        Some((_, Value::Number(n))) if n.as_i64().is_some() => n.as_i64(),
+        Some((config_path, _)) => {
+            return Err(Box::new(ValueError::from(format!(
+                "`leech_suspend_days` key was not an integer in {}",
+                config_path.display()
+            ))))
+        }
+        None => None,
+    };
+
+    let (mut mapping, body) = frontmatter::read_fm_and_body(card.path())?;
+    let entry = history::card_mapping(&mut mapping, card)?;
+    let mut tags = read_strings(entry, "tags", card.path())?;
+    tags.push(LEECH_TAG.to_string());
+    entry.insert(
+        Value::String(String::from("tags")),
+        Value::Sequence(tags.into_iter().map(Value::String).collect()),
     );
-    Value::Mapping(mapping)
+    if let Some(days) = suspend_days {
+        entry.insert(
+            Value::String(String::from("suspended_until")),
+            Value::String(
+                (Utc::today() + Duration::days(days))
+                    .format(history::DATE_FORMAT)
+                    .to_string(),
+            ),
+        );
+    }
+    frontmatter::write_mapping_and_body(card.path(), mapping, body)?;
+
+    Ok(match suspend_days {
+        Some(_) => LeechAction::Suspended,
+        None => LeechAction::Tagged,
+    })
+}
+
+/// Reverts what `mark_leech` did to `card`.
+pub fn unmark_leech(card: &Card, action: LeechAction) -> Result<(), Box<dyn Error>> {
+    if action == LeechAction::None {
+        return Ok(());
+    }
+    let (mut mapping, body) = frontmatter::read_fm_and_body(card.path())?;
+    let entry = history::card_mapping(&mut mapping, card)?;
+    let tags = read_strings(entry, "tags", card.path())?
+        .into_iter()
+        .filter(|tag| tag != LEECH_TAG)
+        .map(Value::String)
+        .collect::<Vec<Value>>();
+    let key = Value::String(String::from("tags"));
+    if tags.is_empty() {
+        entry.remove(&key);
+    } else {
+        entry.insert(key, Value::Sequence(tags));
+    }
+    if action == LeechAction::Suspended {
+        entry.remove(&Value::String(String::from("suspended_until")));
+    }
+    history::prune_subcards(&mut mapping);
+    frontmatter::write_mapping_and_body(card.path(), mapping, body)
 }
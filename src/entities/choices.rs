@@ -0,0 +1,20 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref CHOICE: Regex = Regex::new(r"(?m)^[ \t]*[-*+] \[([ xX])\] (.*)\n?").unwrap();
+}
+
+/// Returns the options of the markdown task list in `text` along with whether each of them is
+/// correct, which is marked by checking it off with `[x]`.
+pub fn parse(text: &str) -> Vec<(String, bool)> {
+    CHOICE
+        .captures_iter(text)
+        .map(|captures| (captures[2].trim().to_string(), &captures[1] != " "))
+        .collect()
+}
+
+/// Removes the options from `text` so that they can be shown separately.
+pub fn strip(text: &str) -> String {
+    CHOICE.replace_all(text, "").to_string()
+}
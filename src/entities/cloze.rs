@@ -0,0 +1,47 @@
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+lazy_static! {
+    static ref CLOZE: Regex = Regex::new(r"\{\{c(\d+)::(.*?)(?:::(.*?))?\}\}").unwrap();
+}
+
+/// Returns the distinct cloze numbers used in `text` in ascending order, so `{{c1::...}}` and
+/// `{{c3::...}}` give `[1, 3]`.
+pub fn numbers(text: &str) -> Vec<u32> {
+    let mut numbers = CLOZE
+        .captures_iter(text)
+        .filter_map(|captures| captures[1].parse().ok())
+        .collect::<Vec<u32>>();
+    numbers.sort_unstable();
+    numbers.dedup();
+    numbers
+}
+
+/// Replaces the clozes in `text` with their answers, except for those numbered `active`, which
+/// are blanked out (showing their hint if they have one) unless `reveal` is set, in which case
+/// their answer is emphasized.
+pub fn render(text: &str, active: u32, reveal: bool) -> String {
+    CLOZE
+        .replace_all(text, |captures: &Captures| {
+            if captures[1].parse() != Ok(active) {
+                captures[2].to_string()
+            } else if reveal {
+                format!("**{}**", &captures[2])
+            } else {
+                match captures.get(3) {
+                    Some(hint) => format!("[{}]", hint.as_str()),
+                    None => String::from("[...]"),
+                }
+            }
+        })
+        .to_string()
+}
+
+/// Returns the answers of the clozes numbered `active` in `text`, in the order they appear.
+pub fn answers(text: &str, active: u32) -> Vec<String> {
+    CLOZE
+        .captures_iter(text)
+        .filter(|captures| captures[1].parse() == Ok(active))
+        .map(|captures| captures[2].to_string())
+        .collect()
+}
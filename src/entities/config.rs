@@ -0,0 +1,64 @@
+use serde_yaml::{Mapping, Value};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name of the file holding settings that apply to every card in the directory it's in,
+/// including those in subdirectories.
+pub const DIR_CONFIG_FILE_NAME: &str = ".spaced.yml";
+
+/// Returns the path and contents of the user's config file, which is `spaced/config.yml` within
+/// `$XDG_CONFIG_HOME`, or `~/.config` if that isn't set. The contents are empty if the file
+/// doesn't exist.
+pub fn global_config() -> Result<(PathBuf, Mapping), Box<dyn Error>> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => return Ok((PathBuf::new(), Mapping::new())),
+        },
+    };
+    let path = config_dir.join("spaced").join("config.yml");
+    if !path.is_file() {
+        return Ok((path, Mapping::new()));
+    }
+    let config = read_config(&path)?;
+    Ok((path, config))
+}
+
+fn read_config(path: &Path) -> Result<Mapping, Box<dyn Error>> {
+    match serde_yaml::from_str(&fs::read_to_string(path)?)? {
+        Value::Mapping(config) => Ok(config),
+        _ => Ok(Mapping::new()),
+    }
+}
+
+/// Returns the paths and contents of the directory configs that apply to the card at `path`,
+/// from the outermost directory inwards, so that later ones can take precedence.
+pub fn dir_configs(path: &Path) -> Result<Vec<(PathBuf, Mapping)>, Box<dyn Error>> {
+    let path = path.canonicalize()?;
+    let mut configs = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        let config_path = dir.join(DIR_CONFIG_FILE_NAME);
+        if config_path.is_file() {
+            let config = read_config(&config_path)?;
+            configs.push((config_path, config));
+        }
+    }
+    configs.reverse();
+    Ok(configs)
+}
+
+/// Returns the value of `key` from the innermost directory config applying to the card at `path`
+/// that sets it, along with the path of that config.
+pub fn dir_setting(path: &Path, key: &str) -> Result<Option<(PathBuf, Value)>, Box<dyn Error>> {
+    Ok(dir_configs(path)?
+        .into_iter()
+        .rev()
+        .find_map(|(config_path, mut config)| {
+            config
+                .remove(&Value::String(key.to_string()))
+                .map(|value| (config_path, value))
+        }))
+}
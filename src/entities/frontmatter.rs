@@ -14,7 +14,7 @@ pub fn read_fm(path: &Path) -> Result<Mapping, Box<dyn Error>> {
 
 fn produce_consumable(path: &Path) -> Result<Consumable, Box<dyn Error>> {
     let reader = BufReader::new(File::open(path)?);
-    Ok(reader.lines().into_iter().peekable())
+    Ok(reader.lines().peekable())
 }
 
 fn consume_fm(line_iter: &mut Consumable) -> Result<Mapping, Box<dyn Error>> {
@@ -25,13 +25,16 @@ fn consume_fm(line_iter: &mut Consumable) -> Result<Mapping, Box<dyn Error>> {
 }
 
 fn consume_fm_text(line_iter: &mut Consumable) -> Result<Option<String>, Box<dyn Error>> {
-    if let Some(_) = line_iter.next_if(|first| {
-        if let Ok(first) = first {
-            first == "---"
-        } else {
-            false
-        }
-    }) {
+    if line_iter
+        .next_if(|first| {
+            if let Ok(first) = first {
+                first == "---"
+            } else {
+                false
+            }
+        })
+        .is_some()
+    {
         Ok(Some(
             line_iter
                 .map_while(|line| {
@@ -81,3 +84,17 @@ pub fn write_fm_and_body(path: &Path, fm: Value, body: String) -> Result<(), Box
     let fm = serde_yaml::to_string(&fm)?;
     Ok(write!(file, "{}---\n\n{}", fm, body)?)
 }
+
+/// Writes `fm` and `body` to `path`, leaving the frontmatter out altogether if it's empty so that
+/// removing the last key from a file doesn't leave an empty header behind.
+pub fn write_mapping_and_body(
+    path: &Path,
+    fm: Mapping,
+    body: String,
+) -> Result<(), Box<dyn Error>> {
+    if fm.is_empty() {
+        write_body(path, body)
+    } else {
+        write_fm_and_body(path, Value::Mapping(fm), body)
+    }
+}
@@ -0,0 +1,667 @@
+use crate::entities::cards::{self, Card};
+use crate::entities::frontmatter;
+use crate::error::ValueError;
+use chrono::format::ParseError;
+use chrono::{Date, NaiveDate, Utc};
+use lazy_static::lazy_static;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+pub const SIDECAR_FILE_NAME: &str = ".spacedhistory";
+pub const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// A single review of a card.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Review {
+    pub date: Date<Utc>,
+    pub remembered: bool,
+    /// How long the card was shown for before it was graded, in milliseconds. This isn't known for
+    /// reviews recorded before answers were timed, or imported from elsewhere.
+    pub time_ms: Option<u64>,
+    /// The card's answer was shown, but it was skipped rather than graded. Peeks are kept in the
+    /// history, but they aren't reviews as far as scheduling is concerned.
+    pub peeked: bool,
+}
+
+#[derive(Debug)]
+pub enum ReviewHistoryError {
+    DateParseError(ParseError),
+    Read(Box<dyn Error>),
+    ValueError,
+}
+
+impl fmt::Display for ReviewHistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReviewHistoryError::DateParseError(e) => e.fmt(f),
+            ReviewHistoryError::Read(e) => e.fmt(f),
+            ReviewHistoryError::ValueError => write!(f, "ValueError"), // TODO: Determine how this should be formatted
+        }
+    }
+}
+
+impl Error for ReviewHistoryError {}
+
+/// Where the review history of a card is stored.
+///
+/// `Frontmatter` keeps the `reviews` list in the card file itself, while `Sidecar` appends events
+/// to a `.spacedhistory` log in the card's directory so that card files are never touched by
+/// reviews. A directory uses the sidecar backend as soon as it contains a `.spacedhistory` file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backend {
+    Frontmatter,
+    Sidecar,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Backend {
+        match name {
+            "frontmatter" => Backend::Frontmatter,
+            "sidecar" => Backend::Sidecar,
+            _ => panic!(), // Cannot occur because clap will block invalid backend arguments
+        }
+    }
+
+    pub fn for_card(path: &Path) -> Backend {
+        if sidecar_path(path).is_file() {
+            Backend::Sidecar
+        } else {
+            Backend::Frontmatter
+        }
+    }
+
+    pub fn read(self, card: &Card) -> Result<Vec<Review>, ReviewHistoryError> {
+        match self {
+            Backend::Frontmatter => read_frontmatter(card),
+            Backend::Sidecar => read_sidecar(card),
+        }
+    }
+
+    pub fn push(self, card: &Card, review: &Review) -> Result<(), Box<dyn Error>> {
+        match self {
+            Backend::Frontmatter => push_frontmatter(card, review),
+            Backend::Sidecar => push_sidecar(card, review),
+        }
+    }
+
+    /// Removes the most recent review event, returning `false` if there was none to remove.
+    pub fn pop(self, card: &Card) -> Result<bool, Box<dyn Error>> {
+        match self {
+            Backend::Frontmatter => pop_frontmatter(card),
+            Backend::Sidecar => pop_sidecar(card),
+        }
+    }
+
+    pub fn clear(self, card: &Card) -> Result<(), Box<dyn Error>> {
+        match self {
+            Backend::Frontmatter => clear_frontmatter(card),
+            Backend::Sidecar => retain_sidecar(card, |_| false).map(|_| ()),
+        }
+    }
+}
+
+/// Moves the history of every card directly inside `dir` to the `to` backend.
+///
+/// Whole directories are migrated at once because the presence of a `.spacedhistory` file
+/// decides the backend for all cards next to it. Every card's history is read before anything is
+/// written, and the old backend is only cleared once the new one holds all of it, so a failure
+/// part way through never loses history. Returns the cards that were moved.
+pub fn migrate_dir(dir: &Path, to: Backend) -> Result<Vec<Card>, Box<dyn Error>> {
+    let sidecar = dir.join(SIDECAR_FILE_NAME);
+    let from = if sidecar.is_file() {
+        Backend::Sidecar
+    } else {
+        Backend::Frontmatter
+    };
+    if from == to {
+        return Ok(vec![]);
+    }
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().map(|e| e == "md").unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut histories = Vec::new();
+    for path in paths {
+        for card in cards::file_cards(&path)? {
+            let history = from.read(&card)?;
+            histories.push((card, history));
+        }
+    }
+
+    match to {
+        // the sidecar is written in full and then moved into place, since the directory switches
+        // backends as soon as it exists
+        Backend::Sidecar => {
+            let mut lines = Vec::new();
+            for (card, history) in &histories {
+                let key = SidecarKey::for_card(card)?;
+                lines.extend(
+                    history
+                        .iter()
+                        .map(|review| format_sidecar_line(&key, review)),
+                );
+            }
+            let partial = dir.join(format!("{}.partial", SIDECAR_FILE_NAME));
+            let mut file = File::create(&partial)?;
+            for line in lines {
+                writeln!(file, "{}", line)?;
+            }
+            fs::rename(&partial, &sidecar)?;
+            forget_sidecar(&sidecar);
+            for (card, _) in &histories {
+                from.clear(card)?;
+            }
+        }
+        // any history left in the frontmatter is stale while the sidecar is in use, so it's
+        // replaced rather than added to
+        Backend::Frontmatter => {
+            for (card, history) in &histories {
+                to.clear(card)?;
+                for review in history {
+                    to.push(card, review)?;
+                }
+            }
+            fs::remove_file(&sidecar)?;
+            forget_sidecar(&sidecar);
+        }
+    }
+
+    let migrated = histories.into_iter().map(|(card, _)| card).collect();
+    Ok(migrated)
+}
+
+fn parse_review(review: &Value) -> Result<Review, ReviewHistoryError> {
+    let date = match review.get("date") {
+        Some(Value::String(string)) => Date::from_utc(
+            NaiveDate::parse_from_str(string, DATE_FORMAT)
+                .map_err(ReviewHistoryError::DateParseError)?,
+            Utc,
+        ),
+        _ => return Err(ReviewHistoryError::ValueError),
+    };
+    let remembered = match review.get("remembered") {
+        Some(Value::Bool(b)) => *b,
+        _ => return Err(ReviewHistoryError::ValueError),
+    };
+    let time_ms = match review.get("time_ms") {
+        Some(Value::Number(n)) => Some(n.as_u64().ok_or(ReviewHistoryError::ValueError)?),
+        None | Some(Value::Null) => None,
+        _ => return Err(ReviewHistoryError::ValueError),
+    };
+    let peeked = match review.get("peeked") {
+        Some(Value::Bool(b)) => *b,
+        None | Some(Value::Null) => false,
+        _ => return Err(ReviewHistoryError::ValueError),
+    };
+    Ok(Review {
+        date,
+        remembered,
+        time_ms,
+        peeked,
+    })
+}
+
+fn review_item(review: &Review) -> Value {
+    let mut mapping = Mapping::new();
+    mapping.insert(
+        Value::String(String::from("date")),
+        Value::String(review.date.format(DATE_FORMAT).to_string()),
+    );
+    mapping.insert(
+        Value::String(String::from("remembered")),
+        Value::Bool(review.remembered),
+    );
+    if let Some(time_ms) = review.time_ms {
+        mapping.insert(
+            Value::String(String::from("time_ms")),
+            Value::Number(time_ms.into()),
+        );
+    }
+    if review.peeked {
+        mapping.insert(Value::String(String::from("peeked")), Value::Bool(true));
+    }
+    Value::Mapping(mapping)
+}
+
+/// Returns the mapping holding what's specific to `card` in `frontmatter`, such as its `reviews`.
+/// Cards that are alone in their file keep it at the top level, while the others keep it under
+/// `subcards.<key>`.
+pub fn card_entry<'a>(frontmatter: &'a Mapping, card: &Card) -> Option<&'a Mapping> {
+    match card.key() {
+        None => Some(frontmatter),
+        Some(key) => frontmatter
+            .get(&Value::String(String::from("subcards")))
+            .and_then(|subcards| subcards.get(key.as_str()))
+            .and_then(Value::as_mapping),
+    }
+}
+
+/// Returns the `reviews` of `card` in `frontmatter`.
+fn card_reviews<'a>(frontmatter: &'a Mapping, card: &Card) -> Option<&'a Value> {
+    card_entry(frontmatter, card)
+        .and_then(|entry| entry.get(&Value::String(String::from("reviews"))))
+}
+
+/// Returns the mapping that should hold what's specific to `card`, creating its `subcards` entry
+/// if needed.
+pub fn card_mapping<'a>(
+    frontmatter: &'a mut Mapping,
+    card: &Card,
+) -> Result<&'a mut Mapping, Box<dyn Error>> {
+    let subcard = match card.key() {
+        Some(key) => key,
+        None => return Ok(frontmatter),
+    };
+
+    let mut mapping = frontmatter;
+    for key in ["subcards", &subcard] {
+        let value = mapping
+            .entry(Value::String(key.to_string()))
+            .or_insert(Value::Null);
+        if value.is_null() {
+            *value = Value::Mapping(Mapping::new());
+        }
+        mapping = value.as_mapping_mut().ok_or_else(|| {
+            ValueError::from(format!("`{}` key was not of type mapping in {}", key, card))
+        })?;
+    }
+    Ok(mapping)
+}
+
+/// Removes entries under `subcards` that no longer hold anything, and `subcards` itself if it
+/// ends up empty.
+pub fn prune_subcards(frontmatter: &mut Mapping) {
+    let key = Value::String(String::from("subcards"));
+    if let Some(Value::Mapping(subcards)) = frontmatter.get_mut(&key) {
+        let empty = subcards
+            .iter()
+            .filter(|(_, subcard)| subcard.as_mapping().map(|m| m.is_empty()).unwrap_or(false))
+            .map(|(anchor, _)| anchor.clone())
+            .collect::<Vec<_>>();
+        for anchor in empty {
+            subcards.remove(&anchor);
+        }
+        if subcards.is_empty() {
+            frontmatter.remove(&key);
+        }
+    }
+}
+
+fn read_frontmatter(card: &Card) -> Result<Vec<Review>, ReviewHistoryError> {
+    let frontmatter = frontmatter::read_fm(card.path()).map_err(ReviewHistoryError::Read)?;
+    match card_reviews(&frontmatter, card).unwrap_or(&Value::Null) {
+        Value::Sequence(sequence) => sequence.iter().map(parse_review).collect(),
+        Value::Null => Ok(vec![]),
+        _ => Err(ReviewHistoryError::ValueError),
+    }
+}
+
+fn push_frontmatter(card: &Card, review: &Review) -> Result<(), Box<dyn Error>> {
+    let (mut frontmatter, body) = frontmatter::read_fm_and_body(card.path())?;
+    let mapping = card_mapping(&mut frontmatter, card)?;
+
+    match mapping.get_mut(&Value::String(String::from("reviews"))) {
+        Some(Value::Sequence(s)) => s.push(review_item(review)),
+        Some(Value::Null) | None => {
+            mapping.insert(
+                Value::String(String::from("reviews")),
+                Value::Sequence(vec![review_item(review)]),
+            );
+        }
+        _ => panic!("Unsupported frontmatter contents in {}", card),
+    }
+
+    frontmatter::write_fm_and_body(card.path(), Value::Mapping(frontmatter), body)
+}
+
+fn pop_frontmatter(card: &Card) -> Result<bool, Box<dyn Error>> {
+    let (mut frontmatter, body) = frontmatter::read_fm_and_body(card.path())?;
+
+    match card_mapping(&mut frontmatter, card)?.get_mut(&Value::String(String::from("reviews"))) {
+        Some(Value::Sequence(s)) => {
+            if s.pop().is_none() {
+                return Ok(false);
+            }
+        }
+        Some(Value::Null) | None => return Ok(false),
+        _ => panic!("Unsupported frontmatter contents in {}", card),
+    }
+
+    frontmatter::write_fm_and_body(card.path(), Value::Mapping(frontmatter), body)?;
+    Ok(true)
+}
+
+fn clear_frontmatter(card: &Card) -> Result<(), Box<dyn Error>> {
+    let (mut frontmatter, body) = frontmatter::read_fm_and_body(card.path())?;
+
+    if card_reviews(&frontmatter, card).is_none() {
+        return Ok(());
+    }
+    card_mapping(&mut frontmatter, card)?.remove(&Value::String(String::from("reviews")));
+    prune_subcards(&mut frontmatter);
+    frontmatter::write_mapping_and_body(card.path(), frontmatter, body)
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    path.parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(SIDECAR_FILE_NAME)
+}
+
+/// Identifies the events of a single card in a sidecar file.
+///
+/// Events are matched to a file by the card's `id` when it has one so that history survives
+/// renames, and by file name otherwise. Events written before the card was given an id only carry
+/// a file name, so those are still matched by name. Within a file, events are told apart by the
+/// key of the card they belong to, which is stored as their `anchor`.
+struct SidecarKey {
+    card: String,
+    id: Option<String>,
+    anchor: Option<String>,
+}
+
+impl SidecarKey {
+    fn for_card(card: &Card) -> Result<SidecarKey, Box<dyn Error>> {
+        Ok(SidecarKey {
+            card: card
+                .path()
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            id: cached_id(card.path())?,
+            anchor: card.key(),
+        })
+    }
+}
+
+/// The modification time and length of a file, which tell whether what was read from it earlier
+/// is still current.
+type Stamp = (SystemTime, u64);
+
+fn stamp(path: &Path) -> Result<Option<Stamp>, io::Error> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some((metadata.modified()?, metadata.len()))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+lazy_static! {
+    // a single command reads the history of each card several times, so sidecars are only parsed
+    // again once they've changed, and the same goes for the ids of the cards they refer to
+    static ref SIDECARS: Mutex<HashMap<PathBuf, (Stamp, Arc<SidecarIndex>)>> =
+        Mutex::new(HashMap::new());
+    static ref IDS: Mutex<HashMap<PathBuf, (Stamp, Option<String>)>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the id of the card file at `path`, reading it again only if the file has changed.
+fn cached_id(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    let stamp = match stamp(path)? {
+        Some(stamp) => stamp,
+        None => return cards::read_id(path),
+    };
+    // Can be unwrapped safely because nothing panics while the lock is held
+    if let Some((cached, id)) = IDS.lock().unwrap().get(path) {
+        if *cached == stamp {
+            return Ok(id.clone());
+        }
+    }
+    let id = cards::read_id(path)?;
+    IDS.lock()
+        .unwrap()
+        .insert(path.to_path_buf(), (stamp, id.clone()));
+    Ok(id)
+}
+
+/// A file name or id along with the anchor of a card within the file, which together pick out the
+/// events of a single card.
+type EventKey = (String, Option<String>);
+
+/// The events of a sidecar file, parsed once and grouped by the card they belong to.
+#[derive(Default)]
+struct SidecarIndex {
+    /// Each event along with the index of its line in the file.
+    events: Vec<(usize, Value)>,
+    /// The events that carry an id, by id and anchor.
+    by_id: HashMap<EventKey, Vec<usize>>,
+    /// All the events by file name and anchor, along with whether each of them carries an id.
+    by_card: HashMap<EventKey, Vec<(usize, bool)>>,
+}
+
+impl SidecarIndex {
+    fn parse(contents: &str) -> Result<SidecarIndex, ReviewHistoryError> {
+        let mut index = SidecarIndex::default();
+        for (line, text) in contents.lines().enumerate() {
+            let event = match parse_sidecar_line(text)? {
+                Some(event) => event,
+                None => continue,
+            };
+            let i = index.events.len();
+            let anchor = event
+                .get("anchor")
+                .and_then(Value::as_str)
+                .map(|a| a.to_string());
+            let id = event.get("id").and_then(Value::as_str);
+            if let Some(id) = id {
+                index
+                    .by_id
+                    .entry((id.to_string(), anchor.clone()))
+                    .or_default()
+                    .push(i);
+            }
+            if let Some(card) = event.get("card").and_then(Value::as_str) {
+                index
+                    .by_card
+                    .entry((card.to_string(), anchor))
+                    .or_default()
+                    .push((i, id.is_some()));
+            }
+            index.events.push((line, event));
+        }
+        Ok(index)
+    }
+
+    /// Returns the indices of the events of the card identified by `key`, oldest first. Events are
+    /// matched by id when both the card and the event have one, and by file name otherwise.
+    fn matching(&self, key: &SidecarKey) -> Vec<usize> {
+        let by_card = self
+            .by_card
+            .get(&(key.card.clone(), key.anchor.clone()))
+            .into_iter()
+            .flatten();
+        let mut matching = match &key.id {
+            Some(id) => by_card
+                .filter(|(_, has_id)| !has_id)
+                .map(|(i, _)| *i)
+                .chain(
+                    self.by_id
+                        .get(&(id.clone(), key.anchor.clone()))
+                        .into_iter()
+                        .flatten()
+                        .copied(),
+                )
+                .collect::<Vec<usize>>(),
+            None => by_card.map(|(i, _)| *i).collect(),
+        };
+        matching.sort_unstable();
+        matching
+    }
+}
+
+/// Returns the parsed sidecar file next to the card at `path`, which is empty if there is none.
+fn sidecar_index(path: &Path) -> Result<Arc<SidecarIndex>, ReviewHistoryError> {
+    let sidecar = sidecar_path(path);
+    let read_error = |e| ReviewHistoryError::Read(Box::new(e));
+    let stamp = match stamp(&sidecar).map_err(read_error)? {
+        Some(stamp) => stamp,
+        None => return Ok(Arc::default()),
+    };
+    // Can be unwrapped safely because nothing panics while the lock is held
+    if let Some((cached, index)) = SIDECARS.lock().unwrap().get(&sidecar) {
+        if *cached == stamp {
+            return Ok(index.clone());
+        }
+    }
+    let index = Arc::new(SidecarIndex::parse(
+        &fs::read_to_string(&sidecar).map_err(read_error)?,
+    )?);
+    SIDECARS
+        .lock()
+        .unwrap()
+        .insert(sidecar, (stamp, index.clone()));
+    Ok(index)
+}
+
+/// Drops the parsed copy of the sidecar file at `sidecar` after it has been written.
+fn forget_sidecar(sidecar: &Path) {
+    SIDECARS.lock().unwrap().remove(sidecar);
+}
+
+/// Each line of a `.spacedhistory` file is a single-element YAML sequence, so the file as a whole
+/// stays valid YAML while events can be appended without rewriting it.
+fn format_sidecar_line(key: &SidecarKey, review: &Review) -> String {
+    let mut fields = format!("card: \"{}\"", escape(&key.card));
+    if let Some(id) = &key.id {
+        fields += &format!(", id: \"{}\"", escape(id));
+    }
+    if let Some(anchor) = &key.anchor {
+        fields += &format!(", anchor: \"{}\"", escape(anchor));
+    }
+    fields += &format!(
+        ", date: {}, remembered: {}",
+        review.date.format(DATE_FORMAT),
+        review.remembered
+    );
+    if let Some(time_ms) = review.time_ms {
+        fields += &format!(", time_ms: {}", time_ms);
+    }
+    if review.peeked {
+        fields += ", peeked: true";
+    }
+    format!("- {{{}}}", fields)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn parse_sidecar_line(line: &str) -> Result<Option<Value>, ReviewHistoryError> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    let mut events: Vec<Value> =
+        serde_yaml::from_str(line).map_err(|e| ReviewHistoryError::Read(Box::new(e)))?;
+    Ok(events.pop())
+}
+
+fn read_sidecar_lines(path: &Path) -> Result<Vec<String>, io::Error> {
+    match fs::read_to_string(sidecar_path(path)) {
+        Ok(s) => Ok(s.lines().map(|l| l.to_string()).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_sidecar_lines(path: &Path, lines: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let sidecar = sidecar_path(path);
+    let mut file = File::create(&sidecar)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    forget_sidecar(&sidecar);
+    Ok(())
+}
+
+fn read_sidecar(card: &Card) -> Result<Vec<Review>, ReviewHistoryError> {
+    let key = SidecarKey::for_card(card).map_err(ReviewHistoryError::Read)?;
+    let index = sidecar_index(card.path())?;
+    index
+        .matching(&key)
+        .into_iter()
+        .map(|i| parse_review(&index.events[i].1))
+        .collect()
+}
+
+fn push_sidecar(card: &Card, review: &Review) -> Result<(), Box<dyn Error>> {
+    let key = SidecarKey::for_card(card)?;
+    let sidecar = sidecar_path(card.path());
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&sidecar)?;
+    writeln!(file, "{}", format_sidecar_line(&key, review))?;
+    forget_sidecar(&sidecar);
+    Ok(())
+}
+
+fn pop_sidecar(card: &Card) -> Result<bool, Box<dyn Error>> {
+    let mut popped = false;
+    retain_sidecar(card, |_| {
+        if popped {
+            true
+        } else {
+            popped = true;
+            false
+        }
+    })
+    .map(|_| popped)
+}
+
+/// Rewrites the sidecar file next to `card`, keeping only the events for other cards and those
+/// of this card for which `keep` returns true. Events are visited newest first, and lines are
+/// otherwise preserved verbatim.
+fn retain_sidecar<F: FnMut(&Value) -> bool>(
+    card: &Card,
+    mut keep: F,
+) -> Result<(), Box<dyn Error>> {
+    let key = SidecarKey::for_card(card)?;
+    let index = sidecar_index(card.path())?;
+    let mut lines = read_sidecar_lines(card.path())?;
+    for i in index.matching(&key).into_iter().rev() {
+        let (line, event) = &index.events[i];
+        if !keep(event) {
+            lines.remove(*line);
+        }
+    }
+    write_sidecar_lines(card.path(), lines)
+}
+
+/// Adds `id` to the events in the sidecar file next to `path` that were recorded for it by file
+/// name only, so that they keep following the card if it is renamed later on.
+pub fn stamp_sidecar_id(path: &Path, id: &str) -> Result<(), Box<dyn Error>> {
+    let card = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut lines = read_sidecar_lines(path)?;
+    for line in lines.iter_mut() {
+        if let Some(event) = parse_sidecar_line(line)? {
+            if event.get("id").is_none() && event.get("card").and_then(Value::as_str) == Some(&card)
+            {
+                let key = SidecarKey {
+                    card: card.clone(),
+                    id: Some(id.to_string()),
+                    anchor: event
+                        .get("anchor")
+                        .and_then(Value::as_str)
+                        .map(|a| a.to_string()),
+                };
+                *line = format_sidecar_line(&key, &parse_review(&event)?);
+            }
+        }
+    }
+    write_sidecar_lines(path, lines)
+}
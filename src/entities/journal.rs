@@ -0,0 +1,310 @@
+use crate::entities::cards::{self, Card, LeechAction};
+use crate::entities::history::{Backend, DATE_FORMAT};
+use crate::error::ValueError;
+use chrono::{Date, NaiveDate, Utc};
+use serde_yaml::{Mapping, Value};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The number of entries kept in the journal. Once it holds twice as many, the oldest ones are
+/// dropped, so that it isn't rewritten on every review.
+const MAX_ENTRIES: usize = 1000;
+
+/// Something done to a card during review that changed its file, along with what's needed to
+/// revert it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    Remembered,
+    /// What forgetting the card did to it as a leech.
+    Forgotten(LeechAction),
+    /// The card's answer was shown, but it was skipped rather than graded.
+    Peeked,
+    Archived,
+    /// The date the card was suspended until before it was buried.
+    Buried(Option<Date<Utc>>),
+}
+
+/// An event in the journal, which outlives the session it happened in so that it can still be
+/// undone afterwards.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// The canonical path of the card's file, since the journal is shared by every directory.
+    path: PathBuf,
+    /// The id of the card's file, which is used to find it if it has been moved since.
+    id: Option<String>,
+    key: Option<String>,
+    pub date: Date<Utc>,
+    pub event: Event,
+}
+
+impl Entry {
+    /// Creates an entry for `event` happening to `card` today.
+    pub fn new(card: &Card, event: Event) -> Result<Entry, Box<dyn Error>> {
+        Ok(Entry {
+            path: card.path().canonicalize()?,
+            id: cards::read_id(card.path())?,
+            key: card.key(),
+            date: Utc::today(),
+            event,
+        })
+    }
+
+    /// Returns the card the event happened to, as it is now. Cards whose file is no longer where it
+    /// was are looked up by id within the collection it was in.
+    pub fn card(&self) -> Result<Card, Box<dyn Error>> {
+        if self.path.is_file() {
+            if let Some(card) = cards::file_cards(&self.path)?
+                .into_iter()
+                .find(|card| card.key() == self.key)
+            {
+                return Ok(card);
+            }
+        }
+        if let Some(id) = &self.id {
+            let root = cards::collection_root(&self.path);
+            for card in cards::get_cards(&root.to_string_lossy(), "all") {
+                if card.key() == self.key && cards::read_id(card.path())?.as_ref() == Some(id) {
+                    return Ok(card);
+                }
+            }
+        }
+        Err(Box::new(ValueError::from(format!(
+            "{} no longer exists",
+            self.card_name()
+        ))))
+    }
+
+    /// Returns the name of the event, as shown when it's undone.
+    pub fn name(&self) -> &'static str {
+        match self.event {
+            Event::Remembered => "remembered",
+            Event::Forgotten(_) => "forgotten",
+            Event::Peeked => "peeked",
+            Event::Archived => "archived",
+            Event::Buried(_) => "buried",
+        }
+    }
+
+    /// Returns the card the event happened to the way cards are usually displayed, relative to the
+    /// current directory if it's within it.
+    fn card_name(&self) -> String {
+        let path = env::current_dir()
+            .ok()
+            .and_then(|dir| self.path.strip_prefix(dir).ok().map(Path::to_path_buf))
+            .unwrap_or_else(|| self.path.clone());
+        match &self.key {
+            Some(key) => format!("{}#{}", path.display(), key),
+            None => path.display().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.date.format(DATE_FORMAT),
+            self.name(),
+            self.card_name()
+        )
+    }
+}
+
+/// Returns the path of the journal, which is `spaced/journal` within `$XDG_DATA_HOME`, or
+/// `~/.local/share` if that isn't set. Nothing is journaled if neither is available.
+fn journal_path() -> Option<PathBuf> {
+    let data_dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("share"),
+    };
+    Some(data_dir.join("spaced").join("journal"))
+}
+
+/// Adds `entry` to the end of the journal, dropping the oldest entries if it has grown too long.
+pub fn push(entry: &Entry) -> Result<(), Box<dyn Error>> {
+    let path = match journal_path() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let lines = read_lines(&path)?;
+    if lines.len() >= 2 * MAX_ENTRIES {
+        write_lines(&path, &lines[lines.len() - MAX_ENTRIES..])?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(writeln!(file, "{}", format_line(entry))?)
+}
+
+/// Removes the latest occurrence of `entry` from the journal, which is usually the last entry,
+/// unless another session has journaled something since.
+pub fn remove(entry: &Entry) -> Result<(), Box<dyn Error>> {
+    let path = match journal_path() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let mut lines = read_lines(&path)?;
+    let line = format_line(entry);
+    match lines.iter().rposition(|l| *l == line) {
+        // the last entry is cut off the end rather than rewriting the whole journal
+        Some(i) if i + 1 == lines.len() => {
+            let len = fs::metadata(&path)?.len();
+            let file = OpenOptions::new().write(true).open(&path)?;
+            file.set_len(len.saturating_sub(line.len() as u64 + 1))?;
+        }
+        Some(i) => {
+            lines.remove(i);
+            write_lines(&path, &lines)?;
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// Returns the entries in the journal, oldest first.
+pub fn read() -> Result<Vec<Entry>, Box<dyn Error>> {
+    let path = match journal_path() {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    read_lines(&path)?
+        .iter()
+        .map(|line| parse_line(line, &path))
+        .collect()
+}
+
+/// Reverts the changes that the event of `entry` made to its card's file. Reviews are only
+/// removed if they're still the last one of their card, so that later ones aren't lost.
+pub fn revert(entry: &Entry) -> Result<(), Box<dyn Error>> {
+    let card = entry.card()?;
+    match entry.event {
+        Event::Remembered | Event::Forgotten(_) | Event::Peeked => {
+            let remembered = entry.event == Event::Remembered;
+            let peeked = entry.event == Event::Peeked;
+            // peeks are included here, since they're removed the same way as reviews
+            match Backend::for_card(card.path()).read(&card)?.last() {
+                Some(review)
+                    if review.date == entry.date
+                        && review.remembered == remembered
+                        && review.peeked == peeked => {}
+                _ => {
+                    return Err(Box::new(ValueError::from(format!(
+                        "the last review of {} is not the one being undone",
+                        card
+                    ))))
+                }
+            }
+            cards::unmark(&card)?;
+            if let Event::Forgotten(leech_action) = entry.event {
+                cards::unmark_leech(&card, leech_action)?;
+            }
+        }
+        Event::Archived => cards::mark_archived(card.path(), false)?,
+        Event::Buried(suspended_until) => cards::mark_card_suspended(&card, suspended_until)?,
+    }
+    Ok(())
+}
+
+fn write_lines(path: &Path, lines: &[String]) -> Result<(), io::Error> {
+    let mut file = File::create(path)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn read_lines(path: &Path) -> Result<Vec<String>, io::Error> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(s
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| l.to_string())
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+/// Like `.spacedhistory` files, each line of the journal is a single-element YAML sequence, so
+/// that entries can be appended without rewriting it.
+fn format_line(entry: &Entry) -> String {
+    let mut fields = format!("path: \"{}\"", escape(&entry.path.to_string_lossy()));
+    if let Some(id) = &entry.id {
+        fields += &format!(", id: \"{}\"", escape(id));
+    }
+    if let Some(key) = &entry.key {
+        fields += &format!(", key: \"{}\"", escape(key));
+    }
+    fields += &format!(
+        ", date: {}, event: {}",
+        entry.date.format(DATE_FORMAT),
+        entry.name()
+    );
+    match entry.event {
+        Event::Forgotten(LeechAction::Tagged) => fields += ", leech: tagged",
+        Event::Forgotten(LeechAction::Suspended) => fields += ", leech: suspended",
+        Event::Buried(Some(until)) => {
+            fields += &format!(", suspended_until: {}", until.format(DATE_FORMAT))
+        }
+        _ => {}
+    }
+    format!("- {{{}}}", fields)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn parse_line(line: &str, path: &Path) -> Result<Entry, Box<dyn Error>> {
+    let invalid = || {
+        Box::new(ValueError::from(format!(
+            "invalid entry `{}` in {}",
+            line,
+            path.display()
+        )))
+    };
+    let mut entries: Vec<Mapping> = serde_yaml::from_str(line).map_err(|_| invalid())?;
+    let entry = entries.pop().ok_or_else(invalid)?;
+    let string = |key: &str| match entry.get(&Value::String(key.to_string())) {
+        Some(Value::String(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let date = |key: &str| -> Result<Option<Date<Utc>>, Box<dyn Error>> {
+        match string(key) {
+            Some(s) => Ok(Some(Date::from_utc(
+                NaiveDate::parse_from_str(&s, DATE_FORMAT)?,
+                Utc,
+            ))),
+            None => Ok(None),
+        }
+    };
+
+    let event = match string("event").as_deref() {
+        Some("remembered") => Event::Remembered,
+        Some("forgotten") => Event::Forgotten(match string("leech").as_deref() {
+            Some("tagged") => LeechAction::Tagged,
+            Some("suspended") => LeechAction::Suspended,
+            None => LeechAction::None,
+            Some(_) => return Err(invalid()),
+        }),
+        Some("peeked") => Event::Peeked,
+        Some("archived") => Event::Archived,
+        Some("buried") => Event::Buried(date("suspended_until")?),
+        _ => return Err(invalid()),
+    };
+    Ok(Entry {
+        path: PathBuf::from(string("path").ok_or_else(invalid)?),
+        id: string("id"),
+        key: string("key"),
+        date: date("date")?.ok_or_else(invalid)?,
+        event,
+    })
+}
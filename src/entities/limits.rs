@@ -0,0 +1,145 @@
+use crate::entities::cards::{self, Card};
+use crate::entities::config;
+use crate::error::ValueError;
+use chrono::Utc;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// The maximum number of cards to introduce and to review each day, as set by `new_per_day` and
+/// `reviews_per_day` in a config.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    pub new_per_day: Option<usize>,
+    pub reviews_per_day: Option<usize>,
+}
+
+impl Limits {
+    pub fn from_config(config: &Mapping, path: &Path) -> Result<Limits, Box<dyn Error>> {
+        Ok(Limits {
+            new_per_day: read_limit(config, "new_per_day", path)?,
+            reviews_per_day: read_limit(config, "reviews_per_day", path)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.new_per_day.is_none() && self.reviews_per_day.is_none()
+    }
+
+    fn limit(&self, kind: Kind) -> Option<usize> {
+        match kind {
+            Kind::New => self.new_per_day,
+            Kind::Review => self.reviews_per_day,
+        }
+    }
+}
+
+fn read_limit(config: &Mapping, key: &str, path: &Path) -> Result<Option<usize>, Box<dyn Error>> {
+    match config.get(&Value::String(key.to_string())) {
+        Some(Value::Number(n)) if n.as_u64().is_some() => Ok(Some(n.as_u64().unwrap() as usize)),
+        None | Some(Value::Null) => Ok(None),
+        Some(_) => Err(Box::new(ValueError::from(format!(
+            "`{}` key was not a positive integer in {}",
+            key,
+            path.display()
+        )))),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Kind {
+    New,
+    Review,
+}
+
+/// A set of cards sharing limits: either all the cards being reviewed, or a deck, which is a
+/// directory whose `.spaced.yml` sets limits.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Scope {
+    Global,
+    Deck(PathBuf),
+}
+
+/// Returns the cards from `due` that fit within today's limits, in the same order.
+///
+/// `global` applies to all the cards in the collection that `path` belongs to, so that reviewing a
+/// subdirectory still counts the day's reviews elsewhere, while the limits of each deck apply to the
+/// cards within it. Cards count against the limits of a day once they have been reviewed on it,
+/// as new cards if that was their first review, so cards that were already reviewed today are
+/// always kept.
+pub fn apply(due: Vec<Card>, path: &str, global: Limits) -> Result<Vec<Card>, Box<dyn Error>> {
+    let mut limits = HashMap::new();
+    if !global.is_empty() {
+        limits.insert(Scope::Global, global);
+    }
+    let mut card_scopes = Vec::new();
+    for card in &due {
+        let mut scopes = vec![Scope::Global];
+        for (config_path, config) in config::dir_configs(card.path())? {
+            let deck_limits = Limits::from_config(&config, &config_path)?;
+            if !deck_limits.is_empty() {
+                // Can be unwrapped safely because config paths are file paths
+                let deck = Scope::Deck(config_path.parent().unwrap().to_path_buf());
+                limits.insert(deck.clone(), deck_limits);
+                scopes.push(deck);
+            }
+        }
+        card_scopes.push(scopes);
+    }
+    if limits.is_empty() {
+        return Ok(due);
+    }
+
+    let mut counts = HashMap::new();
+    for scope in limits.keys() {
+        let dir = match scope {
+            Scope::Global => cards::collection_root(Path::new(path))
+                .to_string_lossy()
+                .to_string(),
+            Scope::Deck(dir) => dir.to_string_lossy().to_string(),
+        };
+        for card in cards::get_cards(&dir, "all") {
+            if let Some(kind) = reviewed_today(&card)? {
+                *counts.entry((scope.clone(), kind)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut kept = Vec::new();
+    for (card, scopes) in due.into_iter().zip(card_scopes) {
+        if reviewed_today(&card)?.is_some() {
+            kept.push(card);
+            continue;
+        }
+        let kind = if cards::read_review_history(&card)?.is_empty() {
+            Kind::New
+        } else {
+            Kind::Review
+        };
+        let fits = scopes.iter().all(|scope| {
+            match limits.get(scope).and_then(|limits| limits.limit(kind)) {
+                Some(limit) => counts.get(&(scope.clone(), kind)).copied().unwrap_or(0) < limit,
+                None => true,
+            }
+        });
+        if fits {
+            for scope in scopes {
+                *counts.entry((scope, kind)).or_insert(0) += 1;
+            }
+            kept.push(card);
+        }
+    }
+    Ok(kept)
+}
+
+/// Returns whether `card` was reviewed today, and if so, whether it was new.
+fn reviewed_today(card: &Card) -> Result<Option<Kind>, Box<dyn Error>> {
+    let history = cards::read_review_history(card)?;
+    let today = Utc::today();
+    Ok(match (history.first(), history.last()) {
+        (Some(first), _) if first.date == today => Some(Kind::New),
+        (_, Some(last)) if last.date == today => Some(Kind::Review),
+        _ => None,
+    })
+}
@@ -1,4 +1,11 @@
 pub mod algorithms;
 pub mod cards;
+pub mod choices;
+pub mod cloze;
+pub mod config;
 pub mod frontmatter;
+pub mod history;
+pub mod journal;
+pub mod limits;
 pub mod notes;
+pub mod query;
@@ -19,10 +19,9 @@ pub fn get_notes(path: &str, all: bool) -> Vec<DirEntry> {
                 Ok(cp) => cp,
                 Err(_) => return None,
             };
-            if canonical_path
+            if !canonical_path
                 .components()
-                .find(|c| c == &Component::Normal(OsStr::new("notes")))
-                .is_none()
+                .any(|c| c == Component::Normal(OsStr::new("notes")))
             {
                 return None;
             }
@@ -36,7 +35,6 @@ pub fn get_notes(path: &str, all: bool) -> Vec<DirEntry> {
                 None
             }
         })
-        .into_iter()
         .collect::<Vec<DirEntry>>()
 }
 
@@ -0,0 +1,335 @@
+use crate::entities::cards::{self, Card, CardState};
+use crate::error::ValueError;
+use chrono::Utc;
+use regex::Regex;
+use std::error::Error;
+
+/// An expression selecting cards by their frontmatter and scheduling state, such as
+/// `tag:rust due:<3d lapses:>4 path:net/** -archived`.
+///
+/// Queries consist of whitespace separated terms, all of which must match. Each term can be
+/// negated by prefixing it with `-`. The supported terms are:
+///
+/// - `tag:a,b`: the card has at least one of the given tags
+/// - `path:glob`: the card's path, or a trailing part of it, matches the glob, in which `*`
+///   matches within a directory and `**` matches across directories
+/// - `due:<3d`, `interval:>=2w`, `reviews:0`, `lapses:>4`: compares the days until the card is due,
+///   the days between its last review and due date, its number of reviews, or its number of
+///   lapses; `<`, `<=`, `>`, `>=` and `=` are supported, with `=` being the default, and
+///   durations can be given in days (`d`, the default) or weeks (`w`)
+/// - `last:>7d`: compares the days since the card was last reviewed; cards that have never been
+///   reviewed don't match
+/// - `due`, `new`, `archived`, `suspended`: the card is due today or earlier, has never been
+///   reviewed, is archived, or is suspended or buried
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+struct Term {
+    negated: bool,
+    filter: Filter,
+}
+
+enum Filter {
+    Tags(Vec<String>),
+    Path(Regex),
+    Due(Comparison),
+    Interval(Comparison),
+    Reviews(Comparison),
+    Lapses(Comparison),
+    LastReview(Comparison),
+    New,
+    Archived,
+    Suspended,
+}
+
+struct Comparison {
+    operator: Operator,
+    value: i64,
+}
+
+enum Operator {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query, ValueError> {
+        Ok(Query {
+            terms: query
+                .split_whitespace()
+                .map(parse_term)
+                .collect::<Result<Vec<Term>, _>>()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn matches(&self, card: &Card, state: &CardState) -> bool {
+        self.terms
+            .iter()
+            .all(|term| term.filter.matches(card, state) != term.negated)
+    }
+
+    /// Returns the cards among `cards` that match this query, with their due dates computed
+    /// according to `algorithm`.
+    pub fn filter(&self, cards: Vec<Card>, algorithm: &str) -> Result<Vec<Card>, Box<dyn Error>> {
+        if self.is_empty() {
+            return Ok(cards);
+        }
+        let mut matching = Vec::new();
+        for card in cards {
+            if self.matches(&card, &cards::read_state(&card, algorithm)?) {
+                matching.push(card);
+            }
+        }
+        Ok(matching)
+    }
+}
+
+impl Filter {
+    fn matches(&self, card: &Card, state: &CardState) -> bool {
+        match self {
+            Filter::Tags(tags) => tags.iter().any(|tag| state.tags.contains(tag)),
+            Filter::Path(glob) => glob.is_match(
+                card.path()
+                    .to_string_lossy()
+                    .trim_start_matches("./")
+                    .as_ref(),
+            ),
+            Filter::Due(comparison) => comparison.matches((state.due - Utc::today()).num_days()),
+            Filter::Interval(comparison) => comparison.matches(state.interval),
+            Filter::Reviews(comparison) => comparison.matches(state.reviews as i64),
+            Filter::Lapses(comparison) => comparison.matches(state.lapses as i64),
+            Filter::LastReview(comparison) => state
+                .last_review
+                .map(|date| comparison.matches((Utc::today() - date).num_days()))
+                .unwrap_or(false),
+            Filter::New => state.reviews == 0,
+            Filter::Archived => state.archived,
+            Filter::Suspended => state
+                .suspended_until
+                .map(|until| until > Utc::today())
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl Comparison {
+    fn matches(&self, value: i64) -> bool {
+        match self.operator {
+            Operator::Less => value < self.value,
+            Operator::LessOrEqual => value <= self.value,
+            Operator::Equal => value == self.value,
+            Operator::GreaterOrEqual => value >= self.value,
+            Operator::Greater => value > self.value,
+        }
+    }
+}
+
+fn parse_term(term: &str) -> Result<Term, ValueError> {
+    let (negated, rest) = match term.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, term),
+    };
+    let invalid = || ValueError::from(format!("invalid query term `{}`", term));
+
+    let filter = match rest.split_once(':') {
+        Some(("tag", tags)) if !tags.is_empty() => {
+            Filter::Tags(tags.split(',').map(|tag| tag.to_string()).collect())
+        }
+        Some(("path", glob)) if !glob.is_empty() => Filter::Path(glob_regex(glob)),
+        Some(("due", value)) => Filter::Due(parse_comparison(value).ok_or_else(invalid)?),
+        Some(("interval", value)) => Filter::Interval(parse_comparison(value).ok_or_else(invalid)?),
+        Some(("reviews", value)) => Filter::Reviews(parse_comparison(value).ok_or_else(invalid)?),
+        Some(("lapses", value)) => Filter::Lapses(parse_comparison(value).ok_or_else(invalid)?),
+        Some(("last", value)) => Filter::LastReview(parse_comparison(value).ok_or_else(invalid)?),
+        None if rest == "due" => Filter::Due(Comparison {
+            operator: Operator::LessOrEqual,
+            value: 0,
+        }),
+        None if rest == "new" => Filter::New,
+        None if rest == "archived" => Filter::Archived,
+        None if rest == "suspended" => Filter::Suspended,
+        _ => return Err(invalid()),
+    };
+    Ok(Term { negated, filter })
+}
+
+fn parse_comparison(value: &str) -> Option<Comparison> {
+    let (operator, value) = if let Some(value) = value.strip_prefix("<=") {
+        (Operator::LessOrEqual, value)
+    } else if let Some(value) = value.strip_prefix(">=") {
+        (Operator::GreaterOrEqual, value)
+    } else if let Some(value) = value.strip_prefix('<') {
+        (Operator::Less, value)
+    } else if let Some(value) = value.strip_prefix('>') {
+        (Operator::Greater, value)
+    } else {
+        (Operator::Equal, value.strip_prefix('=').unwrap_or(value))
+    };
+    let (value, multiplier) = if let Some(value) = value.strip_suffix('w') {
+        (value, 7)
+    } else {
+        (value.strip_suffix('d').unwrap_or(value), 1)
+    };
+    Some(Comparison {
+        operator,
+        value: value.parse::<i64>().ok()? * multiplier,
+    })
+}
+
+/// Converts `glob` into a regex matching paths that end with a match of the glob, so that
+/// `net/**` matches `cards/net/tcp.md`.
+fn glob_regex(glob: &str) -> Regex {
+    let mut pattern = String::from("(^|/)");
+    let mut chars = glob.trim_start_matches("./").chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern += ".*";
+            }
+            '*' => pattern += "[^/]*",
+            '?' => pattern += "[^/]",
+            c => pattern += &regex::escape(&c.to_string()),
+        }
+    }
+    pattern += "$";
+    Regex::new(&pattern).unwrap() // Can be unwrapped safely because everything else is escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the comparison of `term`, which must be a `due:` term.
+    fn due(term: &str) -> Comparison {
+        match parse_term(term).unwrap().filter {
+            Filter::Due(comparison) => comparison,
+            _ => panic!("`{}` didn't parse as a due term", term),
+        }
+    }
+
+    #[test]
+    fn comparisons_support_every_operator() {
+        let less = parse_comparison("<3").unwrap();
+        assert!(less.matches(2) && !less.matches(3));
+        let less_or_equal = parse_comparison("<=3").unwrap();
+        assert!(less_or_equal.matches(3) && !less_or_equal.matches(4));
+        let greater = parse_comparison(">3").unwrap();
+        assert!(greater.matches(4) && !greater.matches(3));
+        let greater_or_equal = parse_comparison(">=3").unwrap();
+        assert!(greater_or_equal.matches(3) && !greater_or_equal.matches(2));
+        for value in ["=3", "3"] {
+            let equal = parse_comparison(value).unwrap();
+            assert!(equal.matches(3) && !equal.matches(2) && !equal.matches(4));
+        }
+    }
+
+    #[test]
+    fn comparisons_count_durations_in_days_or_weeks() {
+        assert_eq!(parse_comparison("<3d").unwrap().value, 3);
+        assert_eq!(parse_comparison("3").unwrap().value, 3);
+        assert_eq!(parse_comparison(">=2w").unwrap().value, 14);
+        assert_eq!(parse_comparison("-1d").unwrap().value, -1);
+    }
+
+    #[test]
+    fn comparisons_reject_values_that_are_not_numbers() {
+        for value in ["", "<", "3m", "d", "w", "<<3", "3dd", "three"] {
+            assert!(
+                parse_comparison(value).is_none(),
+                "`{}` was accepted",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn terms_can_be_negated() {
+        assert!(!parse_term("archived").unwrap().negated);
+        let term = parse_term("-archived").unwrap();
+        assert!(term.negated);
+        assert!(matches!(term.filter, Filter::Archived));
+        let term = parse_term("-tag:a,b").unwrap();
+        assert!(term.negated);
+        assert!(matches!(term.filter, Filter::Tags(tags) if tags == ["a", "b"]));
+    }
+
+    #[test]
+    fn bare_due_means_due_today_or_earlier() {
+        let today = due("due");
+        assert!(matches!(today.operator, Operator::LessOrEqual));
+        assert_eq!(today.value, 0);
+        let soon = due("due:<3d");
+        assert!(matches!(soon.operator, Operator::Less));
+        assert_eq!(soon.value, 3);
+    }
+
+    #[test]
+    fn bare_flags_parse_to_their_filters() {
+        assert!(matches!(parse_term("new").unwrap().filter, Filter::New));
+        assert!(matches!(
+            parse_term("archived").unwrap().filter,
+            Filter::Archived
+        ));
+        assert!(matches!(
+            parse_term("suspended").unwrap().filter,
+            Filter::Suspended
+        ));
+    }
+
+    #[test]
+    fn invalid_terms_are_rejected() {
+        for term in [
+            "",
+            "-",
+            "tag:",
+            "path:",
+            "due:",
+            "due:soon",
+            "lapses:>x",
+            "last:3m",
+            "reviews",
+            "foo",
+            "foo:1",
+            "-new:1",
+        ] {
+            assert!(parse_term(term).is_err(), "`{}` was accepted", term);
+        }
+        assert!(Query::parse("tag:rust bogus").is_err());
+        assert!(Query::parse("  ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn single_stars_match_within_a_directory() {
+        let glob = glob_regex("net/*.md");
+        assert!(glob.is_match("cards/net/tcp.md"));
+        assert!(glob.is_match("net/tcp.md"));
+        assert!(!glob.is_match("cards/net/ip/v4.md"));
+        assert!(!glob.is_match("cards/subnet/tcp.md"));
+    }
+
+    #[test]
+    fn double_stars_match_across_directories() {
+        let glob = glob_regex("net/**");
+        assert!(glob.is_match("cards/net/tcp.md"));
+        assert!(glob.is_match("cards/net/ip/v4.md"));
+        assert!(!glob.is_match("cards/os/tcp.md"));
+    }
+
+    #[test]
+    fn globs_escape_everything_but_wildcards() {
+        let glob = glob_regex("./c++/?.md");
+        assert!(glob.is_match("cards/c++/a.md"));
+        assert!(!glob.is_match("cards/c++/ab.md"));
+        assert!(!glob.is_match("cards/cc/a.md"));
+        assert!(!glob_regex("a.md").is_match("cards/aamd"));
+    }
+}
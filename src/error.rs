@@ -11,7 +11,6 @@ impl ValueError {
         ValueError { message: None }
     }
 
-    #[cfg(feature = "import")]
     pub fn from(message: String) -> Self {
         ValueError {
             message: Some(message),
@@ -3,20 +3,13 @@
 // TODO: add comments to a bunch of stuff, and look into how to properly document rust functions
 // TODO: support day turnover after midnight
 // TODO: display forgotten progress as red instead of green
-// TODO: randomize question order within `cards` folders, but keep each separate folder in a chunk together, also, review questions that have already been seen that day after ones that haven't
-// TODO: display folder containing question
-// TODO: prevent skip after flipping a card
 // TODO: add automatic tests
 // TODO: package for AUR
-// TODO: display path when reviewing card
 // TODO: support `.spacedignore` files (this could be a separate crate if some else hasn't already done it)
-// TODO: support `.spacedhistory` for stats purposes
-// TODO: display time taken so far
 // TODO: unify error handling
 // TODO: add config file support and allow for location arguments in notes and review subcommands
 // with intelligent detection of whether there are globs so that `cards` and `notes` aren't
 // hardcoded subdirectory names
-// TODO: re-add printed stats after review
 // TODO: fix assumption of \n line endings
 // TODO: rename to spc after the current refactor
 
@@ -35,6 +28,7 @@ fn main() {
         Some("import") => import(matches.subcommand_matches("import").unwrap()), // Can be unwrapped safely because clap will ensure the format argument is present
         Some("notes") => notes(matches.subcommand_matches("notes")),
         Some("review") | None => review(matches.subcommand_matches("review")).unwrap(), // TODO: make all subcommands return results and handle them here
+        Some("undo") => undo(matches.subcommand_matches("undo")).unwrap(),
         _ => panic!(), // Cannot occur since no other subcommands are specified
     }
 }
@@ -1,9 +1,13 @@
-use crate::entities::frontmatter;
+use chrono::{Date, NaiveDate, Utc};
 use crossterm::style::Stylize;
-use serde_yaml::Value;
+use std::collections::BTreeSet;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
-use crate::entities::cards;
+use crate::entities::cards::{self, Card, CardState};
+use crate::entities::history::{self, DATE_FORMAT};
+use crate::entities::query::Query;
 
 pub fn cards(matches: Option<&clap::ArgMatches>) {
     match matches {
@@ -11,24 +15,66 @@ pub fn cards(matches: Option<&clap::ArgMatches>) {
             Some("clear-history") | None => {
                 cards_clear_history(m.subcommand_matches("clear-history"))
             }
+            Some("list") => cards_list(m.subcommand_matches("list").unwrap()),
+            Some("archive") => cards_archive(m.subcommand_matches("archive").unwrap(), true),
+            Some("unarchive") => cards_archive(m.subcommand_matches("unarchive").unwrap(), false),
+            Some("suspend") => cards_suspend(m.subcommand_matches("suspend").unwrap()),
+            Some("unsuspend") => cards_unsuspend(m.subcommand_matches("unsuspend").unwrap()),
+            Some("leeches") => cards_leeches(m.subcommand_matches("leeches").unwrap()),
+            Some("assign-ids") => cards_assign_ids(m.subcommand_matches("assign-ids").unwrap()),
+            Some("migrate-history") => {
+                cards_migrate_history(m.subcommand_matches("migrate-history").unwrap())
+            }
             _ => {} // Cannot occur since no other subcommands are specified in ../cli.yaml
         },
         None => cards_clear_history(None),
     }
 }
 
-fn cards_clear_history(matches: Option<&clap::ArgMatches>) {
-    let (path, no_confirm) = match matches {
-        Some(m) => (
-            m.value_of("PATH").unwrap_or("."),
-            m.is_present("no-confirm"),
-        ),
-        _ => (".", false),
+/// Returns all the cards within the `PATH` arguments that match the `query` argument.
+fn matching_cards(matches: Option<&clap::ArgMatches>) -> Vec<Card> {
+    let paths = matches
+        .and_then(|m| m.values_of("PATH"))
+        .map(|values| values.collect::<Vec<&str>>())
+        .unwrap_or_else(|| vec!["."]);
+    let query = match Query::parse(
+        matches
+            .and_then(|m| m.value_of("query"))
+            .unwrap_or_default(),
+    ) {
+        Ok(q) => q,
+        // mistyped queries are common enough that they're reported rather than panicking
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
     };
 
-    let cards = cards::get_cards(path, "all");
+    let cards = paths
+        .into_iter()
+        .flat_map(|path| cards::get_cards(path, "all"))
+        .collect();
+    // the leitner algorithm is used so that due dates in the query are meaningful
+    match query.filter(cards, "leitner") {
+        Ok(cards) => cards,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Returns the paths of the files holding the cards returned by `matching_cards`.
+fn matching_paths(matches: &clap::ArgMatches) -> BTreeSet<PathBuf> {
+    matching_cards(Some(matches))
+        .iter()
+        .map(|card| card.path().to_path_buf())
+        .collect()
+}
+
+fn cards_clear_history(matches: Option<&clap::ArgMatches>) {
+    let no_confirm = matches.map(|m| m.is_present("no-confirm")).unwrap_or(false);
+
+    let cards = matching_cards(matches);
 
-    if cards.len() == 0 {
+    if cards.is_empty() {
         eprintln!("No cards found to review");
         return;
     }
@@ -40,8 +86,8 @@ fn cards_clear_history(matches: Option<&clap::ArgMatches>) {
         );
     }
 
-    for entry in cards.clone() {
-        println!("{}", entry.path().display());
+    for card in cards.clone() {
+        println!("{}", card);
     }
 
     loop {
@@ -62,24 +108,248 @@ fn cards_clear_history(matches: Option<&clap::ArgMatches>) {
         };
     }
 
-    for entry in cards {
-        let (mut mapping, body) = match frontmatter::read_fm_and_body(entry.path()) {
-            Ok(fm) => fm,
+    for card in cards {
+        if let Err(e) = cards::clear_review_history(&card) {
+            panic!("{}", e);
+        }
+    }
+}
+
+const LIST_COLUMNS: [&str; 9] = [
+    "path",
+    "due",
+    "interval",
+    "reviews",
+    "lapses",
+    "last_review",
+    "avg_time_ms",
+    "archived",
+    "suspended_until",
+];
+
+fn cards_list(matches: &clap::ArgMatches) {
+    let mut rows = matching_cards(Some(matches))
+        .into_iter()
+        .map(|card| match cards::read_state(&card, "leitner") {
+            Ok(state) => (card, state),
+            Err(e) => panic!("{}", e),
+        })
+        .collect::<Vec<(Card, CardState)>>();
+    if matches.is_present("archived") {
+        rows.retain(|(_, state)| state.archived);
+    }
+
+    match matches.value_of("sort").unwrap_or("path") {
+        "path" => rows.sort_by_key(|(card, _)| card.to_string()),
+        "due" => rows.sort_by_key(|(_, state)| state.due),
+        "interval" => rows.sort_by_key(|(_, state)| state.interval),
+        "reviews" => rows.sort_by_key(|(_, state)| state.reviews),
+        "lapses" => rows.sort_by_key(|(_, state)| state.lapses),
+        "last-review" => rows.sort_by_key(|(_, state)| state.last_review),
+        "avg-time" => rows.sort_by_key(|(_, state)| state.average_time_ms),
+        _ => panic!(), // Cannot occur because clap will block invalid sort arguments
+    }
+    if matches.is_present("reverse") {
+        rows.reverse();
+    }
+
+    print_rows(&rows, matches.value_of("format").unwrap_or("table"));
+}
+
+fn cards_leeches(matches: &clap::ArgMatches) {
+    let mut rows = Vec::new();
+    for card in matching_cards(Some(matches)) {
+        let state = match cards::read_state(&card, "leitner") {
+            Ok(state) => state,
             Err(e) => panic!("{}", e),
         };
+        let threshold = match cards::leech_threshold(card.path()) {
+            Ok(threshold) => threshold,
+            Err(e) => panic!("{}", e),
+        };
+        if state.lapses >= threshold || state.tags.iter().any(|tag| tag == cards::LEECH_TAG) {
+            rows.push((card, state));
+        }
+    }
+    rows.sort_by_key(|(_, state)| std::cmp::Reverse(state.lapses));
 
-        mapping.remove(&Value::String(String::from("reviews")));
+    print_rows(&rows, matches.value_of("format").unwrap_or("table"));
+}
 
-        if mapping.len() > 0 {
-            match frontmatter::write_fm_and_body(entry.path(), Value::Mapping(mapping), body) {
-                Ok(_) => {}
-                Err(e) => panic!("{}", e),
+/// Prints the `cards list` columns for `rows` in the given format.
+fn print_rows(rows: &[(Card, CardState)], format: &str) {
+    match format {
+        "table" => {
+            let cells = rows
+                .iter()
+                .map(|(card, state)| list_cells(card, state, "-"))
+                .collect::<Vec<[String; 9]>>();
+            let widths = (0..LIST_COLUMNS.len())
+                .map(|i| {
+                    cells
+                        .iter()
+                        .map(|row| row[i].chars().count())
+                        .chain([LIST_COLUMNS[i].len()])
+                        .max()
+                        .unwrap() // Can be unwrapped safely because the header is always present
+                })
+                .collect::<Vec<usize>>();
+            let print_row = |row: Vec<&str>| {
+                let line = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                    .collect::<Vec<String>>()
+                    .join("  ");
+                println!("{}", line.trim_end());
             };
-        } else {
-            match frontmatter::write_body(entry.path(), body) {
-                Ok(_) => {}
+            print_row(LIST_COLUMNS.to_vec());
+            for row in &cells {
+                print_row(row.iter().map(String::as_str).collect());
+            }
+        }
+        "csv" => {
+            println!("{}", LIST_COLUMNS.join(","));
+            for (card, state) in rows {
+                println!(
+                    "{}",
+                    list_cells(card, state, "")
+                        .iter()
+                        .map(|cell| csv_escape(cell))
+                        .collect::<Vec<String>>()
+                        .join(",")
+                );
+            }
+        }
+        "json" => {
+            let values = rows
+                .iter()
+                .map(|(card, state)| {
+                    serde_json::json!({
+                        "path": card.to_string(),
+                        "due": state.due.format(DATE_FORMAT).to_string(),
+                        "interval": state.interval,
+                        "reviews": state.reviews,
+                        "lapses": state.lapses,
+                        "last_review": state
+                            .last_review
+                            .map(|date| date.format(DATE_FORMAT).to_string()),
+                        "avg_time_ms": state.average_time_ms,
+                        "archived": state.archived,
+                        "suspended_until": state
+                            .suspended_until
+                            .map(|date| date.format(DATE_FORMAT).to_string()),
+                    })
+                })
+                .collect::<Vec<serde_json::Value>>();
+            match serde_json::to_string_pretty(&values) {
+                Ok(json) => println!("{}", json),
                 Err(e) => panic!("{}", e),
-            };
+            }
+        }
+        _ => panic!(), // Cannot occur because clap will block invalid format arguments
+    }
+}
+
+/// Returns the values of the `cards list` columns for a card, using `missing` for values that
+/// aren't known, such as the last review of cards that haven't been reviewed yet.
+fn list_cells(card: &Card, state: &CardState, missing: &str) -> [String; 9] {
+    [
+        card.to_string(),
+        state.due.format(DATE_FORMAT).to_string(),
+        state.interval.to_string(),
+        state.reviews.to_string(),
+        state.lapses.to_string(),
+        state
+            .last_review
+            .map(|date| date.format(DATE_FORMAT).to_string())
+            .unwrap_or_else(|| missing.to_string()),
+        state
+            .average_time_ms
+            .map(|time_ms| time_ms.to_string())
+            .unwrap_or_else(|| missing.to_string()),
+        state.archived.to_string(),
+        state
+            .suspended_until
+            .map(|date| date.format(DATE_FORMAT).to_string())
+            .unwrap_or_else(|| missing.to_string()),
+    ]
+}
+
+fn csv_escape(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+fn cards_archive(matches: &clap::ArgMatches, archived: bool) {
+    for path in matching_paths(matches) {
+        if let Err(e) = cards::mark_archived(&path, archived) {
+            panic!("{}", e);
+        }
+        println!("{}", path.display());
+    }
+}
+
+fn cards_suspend(matches: &clap::ArgMatches) {
+    // Can be unwrapped safely because clap will ensure the until argument is present
+    let until = matches.value_of("until").unwrap();
+    let until = match NaiveDate::parse_from_str(until, DATE_FORMAT) {
+        Ok(until) => Date::from_utc(until, Utc),
+        Err(e) => {
+            eprintln!("invalid date `{}`: {}", until, e);
+            process::exit(1);
+        }
+    };
+
+    for path in matching_paths(matches) {
+        if let Err(e) = cards::mark_suspended(&path, Some(until)) {
+            panic!("{}", e);
+        }
+        println!("{}", path.display());
+    }
+}
+
+fn cards_unsuspend(matches: &clap::ArgMatches) {
+    for path in matching_paths(matches) {
+        if let Err(e) = cards::mark_suspended(&path, None) {
+            panic!("{}", e);
+        }
+        println!("{}", path.display());
+    }
+}
+
+fn cards_assign_ids(matches: &clap::ArgMatches) {
+    let path = matches.value_of("PATH").unwrap_or(".");
+
+    for entry in cards::get_cards(path, "all") {
+        match cards::assign_id(entry.path()) {
+            Ok((_, true)) => println!("{}", entry.path().display()),
+            Ok((_, false)) => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+fn cards_migrate_history(matches: &clap::ArgMatches) {
+    let path = matches.value_of("PATH").unwrap_or(".");
+    let to = history::Backend::from_name(matches.value_of("to").unwrap()); // Can be unwrapped safely because clap will ensure the to argument is present
+
+    let dirs = cards::get_cards(path, "all")
+        .iter()
+        .filter_map(|entry| entry.path().parent().map(Path::to_path_buf))
+        .collect::<BTreeSet<_>>();
+
+    for dir in dirs {
+        match history::migrate_dir(&dir, to) {
+            Ok(migrated) => {
+                for card in migrated {
+                    println!("{}", card);
+                }
+            }
+            Err(e) => panic!("{}", e),
         }
     }
 }
@@ -1,10 +1,9 @@
-use super::{Card, Deck};
-use crate::entities::frontmatter;
+use super::{existing_ids, import_id, write_deck, Card, Deck};
 use crate::error::ValueError as VE;
 use chrono::{TimeZone, Utc};
 use rusqlite::{params, Connection, OpenFlags, Result};
 use serde_yaml::{Mapping, Sequence, Value};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs::create_dir;
 use std::fs::File;
@@ -12,10 +11,12 @@ use std::io::{prelude::*, Read};
 use std::path::Path;
 
 // TODO: Use model to construct format intelligently
-struct Model {}
+struct Model {
+    cloze: bool,
+}
 
 // TODO: Support media files
-pub fn import(path: &Path, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+pub fn import(path: &Path, out_dir: &Path, single_file: bool) -> Result<(), Box<dyn Error>> {
     let file = File::open(path)?;
     let mut archive = zip::ZipArchive::new(file)?;
     let mut collection;
@@ -47,8 +48,13 @@ pub fn import(path: &Path, out_dir: &Path) -> Result<(), Box<dyn Error>> {
     let deck_models = serde_json::from_str::<serde_json::Value>(&(query_result.1?))?;
     let deck_models = deck_models.as_object().ok_or(VE::new())?;
     let mut models = HashMap::new();
-    for _model in deck_models {
-        models.insert("", Model {});
+    for (id, model) in deck_models {
+        models.insert(
+            id.as_str(),
+            Model {
+                cloze: model.get("type").and_then(|t| t.as_i64()) == Some(1),
+            },
+        );
     }
     let mut decks = Vec::new();
     for (id, data) in deck_info.iter() {
@@ -62,17 +68,17 @@ pub fn import(path: &Path, out_dir: &Path) -> Result<(), Box<dyn Error>> {
         })
     }
 
-    write_decks(decks, out_dir)
+    write_decks(decks, out_dir, &existing_ids(out_dir), single_file)
 }
 
 fn get_cards(
     did: &str,
     conn: &rusqlite::Connection,
-    _models: &HashMap<&str, Model>,
+    models: &HashMap<&str, Model>,
 ) -> Result<Vec<Card>, Box<dyn Error>> {
     let mut statement = conn.prepare(
         &(String::from(
-            "SELECT cards.id, notes.flds, notes.mod
+            "SELECT cards.id, cards.ord, notes.id, notes.mid, notes.flds, notes.mod
 FROM cards
 LEFT JOIN notes ON cards.nid=notes.id
 WHERE cards.did = ?",
@@ -80,25 +86,84 @@ WHERE cards.did = ?",
     )?;
 
     let card_rows = statement
-        .query_map([did], |row| Ok((row.get(0), row.get(1), row.get(2))))?
-        .collect::<Vec<Result<(Result<isize, _>, Result<String, _>, Result<i64, _>), _>>>();
+        .query_map([did], |row| {
+            Ok((
+                row.get(0),
+                row.get(1),
+                row.get(2),
+                row.get(3),
+                row.get(4),
+                row.get(5),
+            ))
+        })?
+        .collect::<Vec<
+            Result<
+                (
+                    Result<isize, _>,
+                    Result<i64, _>,
+                    Result<i64, _>,
+                    Result<i64, _>,
+                    Result<String, _>,
+                    Result<i64, _>,
+                ),
+                _,
+            >,
+        >>();
     let mut cards = Vec::new();
+    // Anki generates one card per cloze number from a single cloze note, but here they all come
+    // from the same file, so cards are grouped by note and their reviews are kept per cloze
+    let mut cloze_notes = HashMap::new();
     for row in card_rows {
         let row = row.unwrap(); // Safe because we explicitly Ok'd the row in the query map
         let id = row.0?;
-        let body = row
-            .1?
-            .split("\u{1f}")
-            .collect::<Vec<&str>>()
-            .join("\n\n---\n\n");
-        // let modified = row.2?;
-        cards.push(Card {
-            created: Utc::now(),
-            // TODO: support fudging file modification times
-            // updated: Utc::now().timezone().timestamp_millis(modified * 1000),
-            reviews: get_reviews(id, conn)?,
-            body,
+        let ord = row.1?;
+        let nid = row.2?;
+        let cloze = models
+            .get(row.3?.to_string().as_str())
+            .map(|model| model.cloze)
+            .unwrap_or(false);
+        let fields = row.4?;
+        // let modified = row.5?;
+
+        if !cloze {
+            cards.push(Card {
+                created: Utc::now(),
+                id: import_id("anki", &id.to_string()),
+                // TODO: support fudging file modification times
+                // updated: Utc::now().timezone().timestamp_millis(modified * 1000),
+                reviews: get_reviews(id, conn)?,
+                subcards: Mapping::new(),
+                body: fields
+                    .split('\u{1f}')
+                    .collect::<Vec<&str>>()
+                    .join("\n\n---\n\n"),
+            });
+            continue;
+        }
+
+        let index = *cloze_notes.entry(nid).or_insert_with(|| {
+            cards.push(Card {
+                created: Utc::now(),
+                id: import_id("anki-note", &nid.to_string()),
+                reviews: Value::Null,
+                subcards: Mapping::new(),
+                body: fields
+                    .split('\u{1f}')
+                    .filter(|field| !field.trim().is_empty())
+                    .collect::<Vec<&str>>()
+                    .join("\n\n---\n\n"),
+            });
+            cards.len() - 1
         });
+        let mut subcard = Mapping::new();
+        subcard.insert(
+            Value::String(String::from("reviews")),
+            get_reviews(id, conn)?,
+        );
+        cards[index].subcards.insert(
+            Value::String(format!("c{}", ord + 1)),
+            Value::Mapping(subcard),
+        );
     }
     Ok(cards)
 }
@@ -118,17 +183,17 @@ WHERE revlog.cid=",
     for row in review_rows.into_iter() {
         let row = row.unwrap(); // Safe because we explicitly Ok'd the row in the query map
         let timestamp = row.0?;
-        let ease = timestamp.clone();
+        let ease = timestamp;
         let mut mapping = Mapping::new();
         mapping.insert(
             Value::String(String::from("date")),
-            Value::String(String::from(
+            Value::String(
                 Utc::now()
                     .timezone()
                     .timestamp_millis(timestamp)
                     .format("%Y-%m-%d")
                     .to_string(),
-            )),
+            ),
         );
         mapping.insert(
             Value::String(String::from("remembered")),
@@ -139,7 +204,12 @@ WHERE revlog.cid=",
     Ok(Value::Sequence(reviews))
 }
 
-fn write_decks(decks: Vec<Deck>, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+fn write_decks(
+    decks: Vec<Deck>,
+    out_dir: &Path,
+    existing_ids: &HashSet<String>,
+    single_file: bool,
+) -> Result<(), Box<dyn Error>> {
     for deck in decks {
         let deck_out_dir = out_dir.join(deck.name);
         match create_dir(deck_out_dir.clone()) {
@@ -150,22 +220,7 @@ fn write_decks(decks: Vec<Deck>, out_dir: &Path) -> Result<(), Box<dyn Error>> {
             },
         }
 
-        let mut i: i32 = 0;
-
-        for card in deck.cards {
-            i += 1;
-            let mut frontmatter = serde_yaml::Mapping::new();
-            frontmatter.insert(
-                serde_yaml::Value::String(String::from("reviews")),
-                card.reviews,
-            );
-
-            frontmatter::write_fm_and_body(
-                &deck_out_dir.join(String::from("card") + &i.to_string() + ".md"),
-                serde_yaml::Value::Mapping(frontmatter),
-                String::from(card.body),
-            )?;
-        }
+        write_deck(deck.cards, &deck_out_dir, existing_ids, single_file)?;
     }
 
     Ok(())
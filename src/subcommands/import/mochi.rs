@@ -1,10 +1,9 @@
-use super::{Card, Deck};
-use crate::entities::frontmatter;
+use super::{existing_ids, import_id, write_deck, Card, Deck};
 use crate::error::ValueError as VE;
 use chrono::DateTime;
 use chrono::{TimeZone, Utc};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs::create_dir;
 use std::fs::File;
@@ -55,7 +54,7 @@ impl<'a> ChildDeck<'a> {
 }
 
 // TODO: Support media files
-pub fn import(path: &Path, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+pub fn import(path: &Path, out_dir: &Path, single_file: bool) -> Result<(), Box<dyn Error>> {
     let file = File::open(path)?;
     let mut archive = zip::ZipArchive::new(file)?;
     let mut file = archive.by_name("data.json")?;
@@ -138,18 +137,15 @@ pub fn import(path: &Path, out_dir: &Path) -> Result<(), Box<dyn Error>> {
 
     let mut child_deck_ids = Vec::new();
     for child_id in child_decks.keys() {
-        child_deck_ids.push(child_id.clone());
+        child_deck_ids.push(*child_id);
     }
 
     for child_id in child_deck_ids {
         let child = child_decks.remove(&child_id).unwrap(); // Guaruanteed to exist since we just got the ids from the deck
         let parent_id = child.parent_id;
-        match root_decks.get_mut(parent_id) {
-            Some(p) => {
-                p.children.push(child.into_deck());
-                continue;
-            }
-            None => {}
+        if let Some(p) = root_decks.get_mut(parent_id) {
+            p.children.push(child.into_deck());
+            continue;
         }
         child_decks
             .get_mut(parent_id)
@@ -161,6 +157,8 @@ pub fn import(path: &Path, out_dir: &Path) -> Result<(), Box<dyn Error>> {
     write_decks(
         root_decks.into_values().collect::<Vec<ParentDeck>>(),
         out_dir,
+        &existing_ids(out_dir),
+        single_file,
     )
 }
 
@@ -172,7 +170,14 @@ fn parse_cards(json_cards: &Vec<Value>) -> Result<Vec<Card>, Box<dyn Error>> {
     Ok(cards)
 }
 
-fn parse_card<'a>(json_card: &'a Value) -> Result<Card, Box<dyn Error>> {
+fn parse_card(json_card: &Value) -> Result<Card, Box<dyn Error>> {
+    let id = json_card
+        .get("~:id")
+        .ok_or(VE::from("missing `~:id` key in card".to_string()))?
+        .as_str()
+        .ok_or(VE::from(
+            "`~:id` key was not of type string in card".to_string(),
+        ))?;
     let created = timestamp_to_date(
         json_card
             .get("~:created-at")
@@ -210,6 +215,8 @@ fn parse_card<'a>(json_card: &'a Value) -> Result<Card, Box<dyn Error>> {
         .to_string();
     Ok(Card {
         created,
+        id: import_id("mochi", id),
+        subcards: serde_yaml::Mapping::new(),
         // updated,
         reviews,
         body,
@@ -258,7 +265,12 @@ fn parse_reviews(json_reviews: &Vec<Value>) -> Result<serde_yaml::Value, Box<dyn
     Ok(serde_yaml::Value::Sequence(reviews))
 }
 
-fn write_decks(decks: Vec<ParentDeck>, out_dir: &Path) -> Result<(), Box<dyn Error>> {
+fn write_decks(
+    decks: Vec<ParentDeck>,
+    out_dir: &Path,
+    existing_ids: &HashSet<String>,
+    single_file: bool,
+) -> Result<(), Box<dyn Error>> {
     for deck in decks {
         let deck_out_dir = out_dir.join(deck.name);
         match create_dir(deck_out_dir.clone()) {
@@ -269,24 +281,9 @@ fn write_decks(decks: Vec<ParentDeck>, out_dir: &Path) -> Result<(), Box<dyn Err
             },
         }
 
-        let mut i: i32 = 0;
-
-        for card in deck.deck.cards {
-            i += 1;
-            let mut frontmatter = serde_yaml::Mapping::new();
-            frontmatter.insert(
-                serde_yaml::Value::String(String::from("reviews")),
-                card.reviews,
-            );
-
-            frontmatter::write_fm_and_body(
-                &deck_out_dir.join(String::from("card") + &i.to_string() + ".md"),
-                serde_yaml::Value::Mapping(frontmatter),
-                String::from(card.body),
-            )?;
-        }
+        write_deck(deck.deck.cards, &deck_out_dir, existing_ids, single_file)?;
 
-        write_decks(deck.children, &deck_out_dir)?;
+        write_decks(deck.children, &deck_out_dir, existing_ids, single_file)?;
     }
 
     Ok(())
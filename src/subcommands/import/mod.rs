@@ -1,9 +1,14 @@
 mod anki;
 mod mochi;
 
+use crate::entities::{cards, frontmatter};
 use chrono::{DateTime, Utc};
-
+use serde_yaml::{Mapping, Value};
+use std::collections::HashSet;
+use std::error::Error;
 use std::path::Path;
+use uuid::Uuid;
+use walkdir::WalkDir;
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 struct Deck<'a> {
@@ -14,27 +19,181 @@ struct Deck<'a> {
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 struct Card {
     created: DateTime<Utc>,
+    id: String,
     // updated: DateTime<Utc>,
     reviews: serde_yaml::Value,
+    /// Histories of the cards generated from this one, such as one per cloze number, keyed the
+    /// same way as the `subcards` frontmatter key.
+    subcards: serde_yaml::Mapping,
     body: String,
 }
 
 pub fn import(matches: &clap::ArgMatches) {
+    let single_file = matches.is_present("single-file");
     match matches.value_of("format").unwrap() {
         "mochi" => {
             mochi::import(
-                &Path::new(matches.value_of("PATH").unwrap()),
-                &Path::new(matches.value_of("OUT_DIR").unwrap()),
+                Path::new(matches.value_of("PATH").unwrap()),
+                Path::new(matches.value_of("OUT_DIR").unwrap()),
+                single_file,
             )
             .unwrap();
         }
         "anki" => {
             anki::import(
-                &Path::new(matches.value_of("PATH").unwrap()),
-                &Path::new(matches.value_of("OUT_DIR").unwrap()),
+                Path::new(matches.value_of("PATH").unwrap()),
+                Path::new(matches.value_of("OUT_DIR").unwrap()),
+                single_file,
             )
             .unwrap();
         }
         _ => panic!(), // Can't happen because clap will ensure one of the previous options is present
     }
 }
+
+/// Derives a stable card id from the id the card had in the format it was imported from, so that
+/// importing the same export twice doesn't produce duplicate cards.
+fn import_id(format: &str, source_id: &str) -> String {
+    Uuid::new_v5(
+        &Uuid::NAMESPACE_URL,
+        format!("spaced:{}:{}", format, source_id).as_bytes(),
+    )
+    .to_string()
+}
+
+/// Returns the ids of the cards already in `out_dir`, so that cards that were imported before
+/// can be skipped. Cards imported into a single deck file are identified by their anchor.
+fn existing_ids(out_dir: &Path) -> HashSet<String> {
+    WalkDir::new(out_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().map(|e| e == "md").unwrap_or(false))
+        .flat_map(|entry| cards::file_cards(entry.path()).unwrap_or_default())
+        .filter_map(|card| match card.anchor() {
+            Some(anchor) => Some(anchor.to_string()),
+            None => cards::read_id(card.path()).ok().flatten(),
+        })
+        .collect()
+}
+
+fn write_deck(
+    cards: Vec<Card>,
+    deck_out_dir: &Path,
+    existing_ids: &HashSet<String>,
+    single_file: bool,
+) -> Result<(), Box<dyn Error>> {
+    if single_file {
+        write_deck_file(cards, &deck_out_dir.join("deck.md"), existing_ids)
+    } else {
+        write_cards(cards, deck_out_dir, existing_ids)
+    }
+}
+
+/// Appends the cards that weren't imported before to the `format: headings` file at `path`. The
+/// first line of each card becomes its heading, and its id becomes the heading's anchor.
+fn write_deck_file(
+    cards: Vec<Card>,
+    path: &Path,
+    existing_ids: &HashSet<String>,
+) -> Result<(), Box<dyn Error>> {
+    let (mut frontmatter, mut body) = if path.exists() {
+        frontmatter::read_fm_and_body(path)?
+    } else {
+        let mut frontmatter = Mapping::new();
+        frontmatter.insert(
+            Value::String(String::from("format")),
+            Value::String(String::from("headings")),
+        );
+        (frontmatter, String::new())
+    };
+    let mut subcards = match frontmatter.remove(&Value::String(String::from("subcards"))) {
+        Some(Value::Mapping(subcards)) => subcards,
+        _ => Mapping::new(),
+    };
+
+    for card in cards {
+        if existing_ids.contains(&card.id) {
+            continue;
+        }
+
+        let text = card.body.trim_start();
+        let (question, rest) = text.split_once('\n').unwrap_or((text, ""));
+        if !body.is_empty() {
+            body += "\n";
+        }
+        body += &format!(
+            "## {} {{#{}}}\n{}\n",
+            question.trim_start_matches('#').trim(),
+            card.id,
+            rest.trim_end()
+        );
+
+        if card.subcards.is_empty() {
+            let mut subcard = Mapping::new();
+            subcard.insert(Value::String(String::from("reviews")), card.reviews);
+            subcards.insert(Value::String(card.id), Value::Mapping(subcard));
+        } else {
+            for (key, subcard) in card.subcards {
+                let key = format!("{}:{}", card.id, key.as_str().unwrap_or_default());
+                subcards.insert(Value::String(key), subcard);
+            }
+        }
+    }
+
+    if !subcards.is_empty() {
+        frontmatter.insert(
+            Value::String(String::from("subcards")),
+            Value::Mapping(subcards),
+        );
+    }
+    frontmatter::write_fm_and_body(path, Value::Mapping(frontmatter), body)
+}
+
+fn write_cards(
+    cards: Vec<Card>,
+    deck_out_dir: &Path,
+    existing_ids: &HashSet<String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut i: i32 = 0;
+
+    for card in cards {
+        if existing_ids.contains(&card.id) {
+            continue;
+        }
+
+        let mut card_path;
+        loop {
+            i += 1;
+            card_path = deck_out_dir.join(String::from("card") + &i.to_string() + ".md");
+            if !card_path.exists() {
+                break;
+            }
+        }
+
+        let mut frontmatter = serde_yaml::Mapping::new();
+        frontmatter.insert(
+            serde_yaml::Value::String(String::from("id")),
+            serde_yaml::Value::String(card.id),
+        );
+        if !card.reviews.is_null() {
+            frontmatter.insert(
+                serde_yaml::Value::String(String::from("reviews")),
+                card.reviews,
+            );
+        }
+        if !card.subcards.is_empty() {
+            frontmatter.insert(
+                serde_yaml::Value::String(String::from("subcards")),
+                serde_yaml::Value::Mapping(card.subcards),
+            );
+        }
+
+        frontmatter::write_fm_and_body(
+            &card_path,
+            serde_yaml::Value::Mapping(frontmatter),
+            card.body,
+        )?;
+    }
+
+    Ok(())
+}
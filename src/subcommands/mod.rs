@@ -3,9 +3,11 @@ mod cards;
 mod import;
 mod notes;
 mod review;
+mod undo;
 
 pub use cards::cards;
 #[cfg(feature = "import")]
 pub use import::import;
 pub use notes::notes;
 pub use review::review;
+pub use undo::undo;
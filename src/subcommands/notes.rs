@@ -26,7 +26,7 @@ pub fn notes(matches: Option<&clap::ArgMatches>) {
         a_time.cmp(&b_time)
     });
 
-    if entries.len() == 0 {
+    if entries.is_empty() {
         return;
     }
 
@@ -36,7 +36,7 @@ pub fn notes(matches: Option<&clap::ArgMatches>) {
         Command::new(editor.clone())
             .args(entries.iter().map(|e| e.path().as_os_str()))
             .status()
-            .expect(&format!("failed to execute {}", editor));
+            .unwrap_or_else(|_| panic!("failed to execute {}", editor));
     } else {
         for entry in entries {
             println!("{}", entry.path().display());
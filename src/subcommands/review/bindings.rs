@@ -0,0 +1,279 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde_yaml::{Mapping, Value};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use crate::error::ValueError;
+
+/// Something a key can be bound to during review.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    Quit,
+    Continue,
+    Forget,
+    Skip,
+    Archive,
+    Bury,
+    Undo,
+    Edit,
+    Header,
+    Help,
+}
+
+impl Command {
+    /// Every command, in the order they're listed in the help overlay.
+    pub const ALL: [Command; 10] = [
+        Command::Continue,
+        Command::Forget,
+        Command::Skip,
+        Command::Archive,
+        Command::Bury,
+        Command::Undo,
+        Command::Edit,
+        Command::Header,
+        Command::Help,
+        Command::Quit,
+    ];
+
+    /// Returns the name of the command in the `keybindings` config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Quit => "quit",
+            Command::Continue => "continue",
+            Command::Forget => "forget",
+            Command::Skip => "skip",
+            Command::Archive => "archive",
+            Command::Bury => "bury",
+            Command::Undo => "undo",
+            Command::Edit => "edit",
+            Command::Header => "header",
+            Command::Help => "help",
+        }
+    }
+
+    /// Returns what the command does, as shown in the help overlay.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Command::Quit => "end the session",
+            Command::Continue => "show the answer, or mark the card as remembered",
+            Command::Forget => "mark the card as forgotten",
+            Command::Skip => "move the card back in the queue without grading it",
+            Command::Archive => "archive the card",
+            Command::Bury => "hide the card until tomorrow",
+            Command::Undo => "undo the last action",
+            Command::Edit => "open the card in $VISUAL or $EDITOR",
+            Command::Header => "show or hide the card's path and history",
+            Command::Help => "show or hide this help",
+        }
+    }
+
+    fn default_keys(&self) -> &'static [&'static str] {
+        match self {
+            Command::Quit => &["q"],
+            Command::Continue => &["space"],
+            Command::Forget => &["f"],
+            Command::Skip => &["s", "l"],
+            Command::Archive => &["a"],
+            Command::Bury => &["b"],
+            Command::Undo => &["u"],
+            Command::Edit => &["e"],
+            Command::Header => &["i"],
+            Command::Help => &["?"],
+        }
+    }
+}
+
+/// A key along with the modifiers held down with it, such as `ctrl+z`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+const KEY_NAMES: [(&str, KeyCode); 16] = [
+    ("space", KeyCode::Char(' ')),
+    ("enter", KeyCode::Enter),
+    ("esc", KeyCode::Esc),
+    ("tab", KeyCode::Tab),
+    ("backtab", KeyCode::BackTab),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+];
+
+impl Key {
+    /// Parses keys such as `q`, `?`, `space`, `left`, `f5` or `ctrl+shift+z`.
+    pub fn parse(key: &str) -> Result<Key, ValueError> {
+        let invalid = || ValueError::from(format!("invalid key `{}`", key));
+
+        // the key itself can be `+`, so it's split off from the end
+        let (prefix, name) = match key.strip_suffix('+') {
+            Some(prefix) if prefix.is_empty() || prefix.ends_with('+') => (prefix, "+"),
+            _ => key.rsplit_once('+').unwrap_or(("", key)),
+        };
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in prefix.split('+').filter(|m| !m.is_empty()) {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(invalid()),
+            };
+        }
+
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => {
+                let lower = name.to_lowercase();
+                match KEY_NAMES.iter().find(|(n, _)| *n == lower) {
+                    Some((_, code)) => *code,
+                    None => match lower.strip_prefix('f').map(str::parse::<u8>) {
+                        Some(Ok(n)) if (1..=12).contains(&n) => KeyCode::F(n),
+                        _ => return Err(invalid()),
+                    },
+                }
+            }
+        };
+        Ok(Key::new(code, modifiers))
+    }
+
+    /// Creates a key, treating shift with a character as the shifted character itself, since
+    /// that's what terminals send.
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => Key {
+                code: KeyCode::Char(c.to_ascii_uppercase()),
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            _ => Key { code, modifiers },
+        }
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Key {
+        Key::new(event.code, event.modifiers)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            code => match KEY_NAMES.iter().find(|(_, c)| *c == code) {
+                Some((name, _)) => write!(f, "{}", name),
+                None => write!(f, "{:?}", code),
+            },
+        }
+    }
+}
+
+/// The keys bound to each command during review.
+pub struct Bindings {
+    bindings: Vec<(Key, Command)>,
+}
+
+impl Bindings {
+    /// Reads the `keybindings` key of a config, which maps command names to a key or a list of
+    /// keys. Commands that aren't listed keep their default keys.
+    pub fn from_config(config: &Mapping, path: &Path) -> Result<Bindings, Box<dyn Error>> {
+        let invalid = |message: String| {
+            Box::new(ValueError::from(format!(
+                "{} in `keybindings` key in {}",
+                message,
+                path.display()
+            )))
+        };
+        let configured = match config.get(&Value::String("keybindings".to_string())) {
+            None | Some(Value::Null) => Mapping::new(),
+            Some(Value::Mapping(configured)) => configured.clone(),
+            Some(_) => return Err(invalid("expected a mapping".to_string())),
+        };
+        for (name, _) in configured.iter() {
+            if !Command::ALL
+                .iter()
+                .any(|command| Value::String(command.name().to_string()) == *name)
+            {
+                return Err(invalid(format!("unknown command {:?}", name)));
+            }
+        }
+
+        let mut bindings = Vec::new();
+        for command in Command::ALL {
+            let keys = match configured.get(&Value::String(command.name().to_string())) {
+                None => command
+                    .default_keys()
+                    .iter()
+                    .map(|key| key.to_string())
+                    .collect(),
+                Some(Value::String(key)) => vec![key.clone()],
+                Some(Value::Sequence(keys)) => keys
+                    .iter()
+                    .map(|key| match key {
+                        Value::String(key) => Ok(key.clone()),
+                        _ => Err(invalid(format!("expected keys for `{}`", command.name()))),
+                    })
+                    .collect::<Result<Vec<String>, _>>()?,
+                Some(_) => {
+                    return Err(invalid(format!("expected keys for `{}`", command.name())));
+                }
+            };
+            for key in keys {
+                bindings.push((Key::parse(&key)?, command));
+            }
+        }
+
+        for (i, (key, command)) in bindings.iter().enumerate() {
+            if let Some((_, other)) = bindings[..i].iter().find(|(k, _)| k == key) {
+                if other != command {
+                    return Err(invalid(format!(
+                        "`{}` is bound to both `{}` and `{}`",
+                        key,
+                        other.name(),
+                        command.name()
+                    )));
+                }
+            }
+        }
+        Ok(Bindings { bindings })
+    }
+
+    /// Returns the command bound to a key press, if any.
+    pub fn command(&self, event: KeyEvent) -> Option<Command> {
+        let key = Key::from(event);
+        self.bindings
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, command)| *command)
+    }
+
+    /// Returns the keys bound to `command`.
+    pub fn keys(&self, command: Command) -> Vec<Key> {
+        self.bindings
+            .iter()
+            .filter(|(_, c)| *c == command)
+            .map(|(key, _)| *key)
+            .collect()
+    }
+}
@@ -0,0 +1,71 @@
+use crossterm::style::{Attribute, Stylize};
+
+pub enum Change {
+    Equal(char),
+    /// A character that was typed but isn't in the expected answer.
+    Extra(char),
+    /// A character of the expected answer that wasn't typed.
+    Missing(char),
+}
+
+/// Computes a character-level diff from `typed` to `expected` using their longest common
+/// subsequence. Answers are short enough that the quadratic table isn't a concern.
+pub fn diff(typed: &str, expected: &str) -> Vec<Change> {
+    let typed = typed.chars().collect::<Vec<char>>();
+    let expected = expected.chars().collect::<Vec<char>>();
+
+    let mut lengths = vec![vec![0_usize; expected.len() + 1]; typed.len() + 1];
+    for i in (0..typed.len()).rev() {
+        for j in (0..expected.len()).rev() {
+            lengths[i][j] = if typed[i] == expected[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < typed.len() || j < expected.len() {
+        if i < typed.len() && j < expected.len() && typed[i] == expected[j] {
+            changes.push(Change::Equal(typed[i]));
+            i += 1;
+            j += 1;
+        } else if j < expected.len() && (i == typed.len() || lengths[i][j + 1] >= lengths[i + 1][j])
+        {
+            changes.push(Change::Missing(expected[j]));
+            j += 1;
+        } else {
+            changes.push(Change::Extra(typed[i]));
+            i += 1;
+        }
+    }
+    changes
+}
+
+/// Renders `changes` on a single line, with extra characters struck out in red and missing ones
+/// underlined in green.
+pub fn render(changes: &[Change]) -> String {
+    changes
+        .iter()
+        .map(|change| match change {
+            Change::Equal(c) => c.to_string(),
+            Change::Extra(c) => c.red().attribute(Attribute::CrossedOut).to_string(),
+            Change::Missing(c) => c.green().underlined().to_string(),
+        })
+        .collect()
+}
+
+/// Whether `typed` should count as remembering `expected`, ignoring case and whitespace
+/// differences.
+pub fn matches(typed: &str, expected: &str) -> bool {
+    normalize(typed) == normalize(expected)
+}
+
+fn normalize(s: &str) -> String {
+    s.split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
@@ -3,7 +3,7 @@
 
 use crossterm::{
     cursor,
-    event::{read, Event, KeyCode, KeyEvent, KeyModifiers},
+    event::{poll, read, Event, KeyCode, KeyEvent},
     execute,
     style::Stylize,
     terminal::{
@@ -13,18 +13,36 @@ use crossterm::{
 };
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use rand::{seq::SliceRandom, thread_rng};
 use regex::Regex;
+use serde_yaml::{Mapping, Value};
 use std::{
     env,
     error::Error,
     io::{self, Write},
-    process::Command,
+    path::Path,
+    process,
+    time::{Duration, Instant},
 };
-use walkdir::DirEntry;
 
-use crate::entities::{cards, frontmatter};
+use crate::entities::cards;
+use crate::entities::config;
+use crate::entities::history::DATE_FORMAT;
+use crate::entities::limits::{self, Limits};
+use crate::entities::query::Query;
+use crate::error::ValueError;
+mod bindings;
 mod converters;
+mod diff;
+mod order;
+mod queue;
+mod session;
+mod summary;
+
+use bindings::{Bindings, Command};
+use session::{
+    chose_correctly, Action, Answer, Event as SessionEvent, ReviewSession, Skip, SkipAfterReveal,
+};
+use summary::Summary;
 
 #[cfg(feature = "pandoc")]
 const BASE16_THEME_BYTES: &[u8; 814] = include_bytes!("../../../assets/base16.themedump");
@@ -34,15 +52,6 @@ const BOX_FULL: &str = "█";
 const BOX_LEFT: [&str; 8] = [BOX_EMPTY, "▏", "▎", "▍", "▌", "▋", "▊", "▉"];
 const BOX_RIGHT: [&str; 8] = [BOX_EMPTY, "▕", "🮇", "🮈", "▐", "🮉", "🮊", "🮋"];
 
-enum UndoItem {
-    MarkRemembered(DirEntry),
-    MarkForgotten,
-    MarkArchived(DirEntry),
-    Skip,
-}
-
-// TODO: create a library and refactor the list of cards into a circular linked list for better
-// performance
 // TODO: handle foresable errors such as reading card bodies better
 // TODO: make sure that write! and execute! are buffering and not actually writing until flush is
 // called
@@ -54,193 +63,251 @@ pub fn review(matches: Option<&clap::ArgMatches>) -> Result<(), Box<dyn Error>>
         ),
         _ => (".", "leitner"),
     };
+    let type_answer = matches
+        .map(|m| m.is_present("type-answer"))
+        .unwrap_or(false);
 
-    let mut cards = cards::get_cards(path, algorithm);
-    if matches.map(|m| !m.is_present("no-shuffle")).unwrap_or(true) {
-        cards.shuffle(&mut thread_rng());
+    let mut query = vec![matches
+        .and_then(|m| m.value_of("query"))
+        .unwrap_or_default()
+        .to_string()];
+    if let Some(tags) = matches.and_then(|m| m.values_of("tag")) {
+        query.push(format!("tag:{}", tags.collect::<Vec<&str>>().join(",")));
     }
-
-    if cards.len() == 0 {
+    if let Some(tags) = matches.and_then(|m| m.values_of("exclude-tag")) {
+        query.extend(tags.map(|tag| format!("-tag:{}", tag)));
+    }
+    let query = match Query::parse(&query.join(" ")) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let summary_format = matches
+        .and_then(|m| m.value_of("summary-format"))
+        .unwrap_or("text");
+    if matches.map(|m| m.is_present("summary")).unwrap_or(false) {
+        let cards = query.filter(cards::get_cards(path, "all"), algorithm)?;
+        Summary::today(&cards, algorithm)?.print(summary_format);
         return Ok(());
     }
 
-    let mut remembered = 0;
-    let mut forgotten = 0;
-    let mut component = 0;
-    let card = frontmatter::read_body(&cards[0].path())?;
-    let mut components: Vec<String> = card.split("\n---\n").map(|s| s.to_string()).collect();
-    let mut undo_stack = Vec::new();
+    let (config_path, config) = config::global_config()?;
+    let mut cards =
+        cards::bury_reviewed_siblings(query.filter(cards::get_cards(path, algorithm), algorithm)?)?;
+    if algorithm != "all" {
+        let mut global_limits = Limits::from_config(&config, &config_path)?;
+        if let Some(n) = matches.and_then(|m| m.value_of("new-per-day")) {
+            global_limits.new_per_day = Some(n.parse()?);
+        }
+        if let Some(n) = matches.and_then(|m| m.value_of("reviews-per-day")) {
+            global_limits.reviews_per_day = Some(n.parse()?);
+        }
+        cards = limits::apply(cards, path, global_limits)?;
+    }
+    let max_cards = match matches.and_then(|m| m.value_of("max-cards")) {
+        Some(n) => Some(n.parse::<usize>()?),
+        None => None,
+    };
+    let time = match matches.and_then(|m| m.value_of("time")) {
+        Some(time) => Some(parse_duration(time)?),
+        None => None,
+    };
+    let strategy = match matches.and_then(|m| m.value_of("order")) {
+        Some(strategy) => strategy,
+        None if matches.map(|m| m.is_present("no-shuffle")).unwrap_or(false) => "walk",
+        // when the session can't fit every card, the most overdue ones should be seen first
+        None if max_cards.is_some() || time.is_some() => "overdue-first",
+        None => "random",
+    };
+    cards = order::order(cards, strategy, algorithm)?;
+    if let Some(max_cards) = max_cards {
+        cards.truncate(max_cards);
+    }
+
+    let learning_steps = learning_steps(&config, &config_path)?;
+    let skip = skip(&config, &config_path)?;
+    let bindings = Bindings::from_config(&config, &config_path)?;
+    let header = match config.get(&Value::String("show_header".to_string())) {
+        Some(Value::Bool(show_header)) => *show_header,
+        None | Some(Value::Null) => true,
+        Some(_) => {
+            return Err(Box::new(ValueError::from(format!(
+                "`show_header` key was not a boolean in {}",
+                config_path.display()
+            ))))
+        }
+    };
+    let deadline = time.map(|time| Instant::now() + time);
+    let mut session = ReviewSession::new(
+        cards,
+        algorithm,
+        learning_steps,
+        type_answer,
+        skip,
+        deadline,
+        Instant::now(),
+    )?;
+    if session.is_finished() {
+        return Ok(());
+    }
 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, cursor::Hide)?;
 
-    print_progress(&mut stdout, remembered, forgotten, cards.len())?;
-    print_card(&mut stdout, component, &components)?;
+    // the terminal is restored even if the session fails part way through
+    let result = run_session(&mut stdout, &mut session, &bindings, header, time);
+    execute!(stdout, LeaveAlternateScreen, cursor::Show)?;
+    disable_raw_mode()?;
+    result?;
+
+    if !session.graded().is_empty() {
+        let cards = query.filter(cards::get_cards(path, "all"), algorithm)?;
+        Summary::new(
+            session.graded(),
+            session.elapsed(Instant::now()),
+            summary::due_tomorrow(&cards, algorithm)?,
+        )
+        .print(summary_format);
+    }
+    Ok(())
+}
+
+/// Shows `session` in the terminal and applies the actions bound to the keys pressed, until it's
+/// finished or quit.
+fn run_session(
+    stdout: &mut io::Stdout,
+    session: &mut ReviewSession,
+    bindings: &Bindings,
+    mut header: bool,
+    time: Option<Duration>,
+) -> Result<(), Box<dyn Error>> {
+    print_progress(
+        stdout,
+        session.remembered(),
+        session.forgotten(),
+        session.remaining(),
+    )?;
+    print_session(stdout, session, header, bindings)?;
     stdout.flush()?;
 
+    let mut help = false;
     loop {
-        match read()? {
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('q'),
-                modifiers: KeyModifiers { .. },
-            }) => break,
-            Event::Key(KeyEvent {
-                code: KeyCode::Char(' '),
-                modifiers: KeyModifiers { .. },
-            }) => {
-                if component == components.len() - 1 {
-                    remembered += 1;
-                    cards::mark(cards[0].path(), true);
-                    undo_stack.push(UndoItem::MarkRemembered(cards.remove(0)));
-                    if cards.len() == 0 {
-                        break;
-                    }
+        // the elapsed time is kept up to date while waiting for keys
+        print_elapsed(stdout, session.elapsed(Instant::now()), time)?;
+        stdout.flush()?;
+        if !poll(Duration::from_secs(1))? {
+            continue;
+        }
 
-                    component = 0;
-                    let card = frontmatter::read_body(&cards[0].path())?;
-                    components = card.split("\n---\n").map(|s| s.to_string()).collect();
-                    print_progress(&mut stdout, remembered, forgotten, cards.len())?;
-                    print_card(&mut stdout, component, &components)?;
+        let key = match read()? {
+            Event::Key(key) => key,
+            Event::Resize(..) => {
+                print_progress(
+                    stdout,
+                    session.remembered(),
+                    session.forgotten(),
+                    session.remaining(),
+                )?;
+                if help {
+                    print_help(stdout, bindings)?;
                 } else {
-                    component += 1;
-                    print_card(&mut stdout, component, &components)?;
+                    print_session(stdout, session, header, bindings)?;
                 }
                 stdout.flush()?;
+                continue;
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('s'),
-                modifiers: KeyModifiers { .. },
-            })
-            | Event::Key(KeyEvent {
-                code: KeyCode::Char('l'),
-                modifiers: KeyModifiers { .. },
-            }) => {
-                let card = cards.remove(0);
-                cards.push(card);
-                undo_stack.push(UndoItem::Skip);
-
-                component = 0;
-                let card = frontmatter::read_body(&cards[0].path())?;
-                components = card.split("\n---\n").map(|s| s.to_string()).collect();
-                print_progress(&mut stdout, remembered, forgotten, cards.len())?;
-                print_card(&mut stdout, component, &components)?;
-                stdout.flush()?;
-            }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('f'),
-                modifiers: KeyModifiers { .. },
-            }) => {
-                forgotten += 1;
-                let card = cards.remove(0);
-                cards::mark(card.path(), false);
-                cards.push(card);
-                undo_stack.push(UndoItem::MarkForgotten);
-
-                component = 0;
-                let card = frontmatter::read_body(&cards[0].path())?;
-                components = card.split("\n---\n").map(|s| s.to_string()).collect();
-                print_progress(&mut stdout, remembered, forgotten, cards.len())?;
-                print_card(&mut stdout, component, &components)?;
-                stdout.flush()?;
-            }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('a'),
-                modifiers: KeyModifiers { .. },
-            }) => {
-                let card = cards.remove(0);
-                if cards.len() == 0 {
-                    break;
-                }
-                undo_stack.push(UndoItem::MarkArchived(card));
+            _ => continue,
+        };
+        // any key closes the help overlay
+        if help {
+            help = false;
+            print_session(stdout, session, header, bindings)?;
+            stdout.flush()?;
+            continue;
+        }
 
-                component = 0;
-                let card = frontmatter::read_body(&cards[0].path())?;
-                components = card.split("\n---\n").map(|s| s.to_string()).collect();
-                print_progress(&mut stdout, remembered, forgotten, cards.len())?;
-                print_card(&mut stdout, component, &components)?;
-                stdout.flush()?;
-            }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('u'),
-                modifiers: KeyModifiers { .. },
-            }) => {
-                if let Some(undo_item) = undo_stack.pop() {
-                    match undo_item {
-                        UndoItem::MarkRemembered(c) => {
-                            remembered -= 1;
-                            cards::unmark(c.path());
-                            cards.insert(0, c);
-                        }
-                        UndoItem::MarkForgotten => {
-                            forgotten -= 1;
-                            let card = cards.pop().unwrap();
-                            cards::unmark(card.path());
-                            cards.insert(0, card);
-                        }
-                        UndoItem::MarkArchived(c) => {
-                            cards::mark_archived(c.path(), false);
-                            cards.insert(0, c);
-                        }
-                        UndoItem::Skip => {
-                            let card = cards.pop().unwrap();
-                            cards.insert(0, card);
-                        }
-                    }
+        let action = match answer_action(session.answer(), key) {
+            Some(action) => action,
+            // while typing, keys are part of the answer rather than bound to commands
+            None if matches!(session.answer(), Answer::Typing(_)) => continue,
+            None => match bindings.command(key) {
+                Some(Command::Quit) => break,
+                Some(Command::Continue) => Action::Continue,
+                Some(Command::Forget) => Action::Forget,
+                Some(Command::Skip) => Action::Skip,
+                Some(Command::Archive) => Action::Archive,
+                Some(Command::Bury) => Action::Bury,
+                Some(Command::Undo) => Action::Undo,
+                Some(Command::Edit) => {
+                    execute!(stdout, LeaveAlternateScreen, cursor::Show)?;
+                    disable_raw_mode()?;
 
-                    component = 0;
-                    let card = frontmatter::read_body(&cards[0].path())?;
-                    components = card.split("\n---\n").map(|s| s.to_string()).collect();
-                    print_progress(&mut stdout, remembered, forgotten, cards.len())?;
-                    print_card(&mut stdout, component, &components)?;
+                    // TODO: extract this code and the code for editing notes into a helper module
+                    let editor = env::var("VISUAL")
+                        .unwrap_or(env::var("EDITOR").expect("please set $VISUAL or $EDITOR"));
+                    // Can be unwrapped safely because the loop ends once the session is finished
+                    process::Command::new(editor.clone())
+                        .args([session.card().unwrap().path().as_os_str()])
+                        .status()
+                        .unwrap_or_else(|_| panic!("failed to execute {}", editor));
+
+                    enable_raw_mode()?;
+                    execute!(stdout, EnterAlternateScreen, cursor::Hide)?;
+                    print_progress(
+                        stdout,
+                        session.remembered(),
+                        session.forgotten(),
+                        session.remaining(),
+                    )?;
+                    Action::Reload
+                }
+                Some(Command::Header) => {
+                    header = !header;
+                    print_session(stdout, session, header, bindings)?;
                     stdout.flush()?;
+                    continue;
                 }
+                Some(Command::Help) => {
+                    help = true;
+                    print_help(stdout, bindings)?;
+                    stdout.flush()?;
+                    continue;
+                }
+                None => continue,
+            },
+        };
+
+        match session.apply(action, Instant::now())? {
+            Some(SessionEvent::Finished) => break,
+            Some(SessionEvent::CardChanged) => {
+                print_progress(
+                    stdout,
+                    session.remembered(),
+                    session.forgotten(),
+                    session.remaining(),
+                )?;
+                print_session(stdout, session, header, bindings)?;
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('e'),
-                modifiers: KeyModifiers { .. },
-            }) => {
-                execute!(stdout, LeaveAlternateScreen, cursor::Show)?;
-                disable_raw_mode()?;
-
-                // TODO: extract this code and the code for editing notes into a helper module
-                let editor = env::var("VISUAL")
-                    .unwrap_or(env::var("EDITOR").expect("please set $VISUAL or $EDITOR"));
-                Command::new(editor.clone())
-                    .args([cards[0].path().as_os_str()])
-                    .status()
-                    .expect(&format!("failed to execute {}", editor));
-
-                component = 0;
-                let card = frontmatter::read_body(&cards[0].path())?;
-                components = card.split("\n---\n").map(|s| s.to_string()).collect();
-
-                enable_raw_mode()?;
-                execute!(stdout, EnterAlternateScreen, cursor::Hide)?;
-                print_progress(&mut stdout, remembered, forgotten, cards.len())?;
-                print_card(&mut stdout, component, &components)?;
-                stdout.flush()?;
-            }
-            Event::Resize(..) => {
-                print_progress(&mut stdout, remembered, forgotten, cards.len())?;
-                print_card(&mut stdout, component, &components)?;
-                stdout.flush()?;
+            Some(SessionEvent::CardUpdated) => print_session(stdout, session, header, bindings)?,
+            Some(SessionEvent::SkipDisallowed) => print_notice(
+                stdout,
+                "Skipping is disallowed once the answer has been shown",
+            )?,
+            Some(SessionEvent::UndoFailed(reason)) => {
+                print_notice(stdout, &format!("Couldn't undo: {}", reason))?
             }
-            _ => (),
-        };
+            None => continue,
+        }
+        stdout.flush()?;
     }
-
-    execute!(stdout, LeaveAlternateScreen, cursor::Show)?;
-    disable_raw_mode()?;
     Ok(())
 }
 
 #[cfg(feature = "pandoc")]
-fn print_card(
-    stdout: &mut io::Stdout,
-    component: usize,
-    components: &Vec<String>,
-) -> Result<(), Box<dyn Error>> {
+fn print_card(stdout: &mut io::Stdout, face: &str, row: u16) -> Result<(), Box<dyn Error>> {
     use pandoc::{InputFormat, InputKind, OutputFormat, OutputKind};
     use pandoc_types::definition::IterBlocks;
 
@@ -249,10 +316,7 @@ fn print_card(
     }
 
     let mut pandoc = pandoc::new();
-    pandoc.set_input(InputKind::Pipe(
-        RE.replace_all(&components[..component + 1].join("\n---\n"), "")
-            .to_string(),
-    ));
+    pandoc.set_input(InputKind::Pipe(RE.replace_all(face, "").to_string()));
     pandoc.set_output(OutputKind::Pipe);
     pandoc.set_input_format(InputFormat::Markdown, Vec::new());
     pandoc.set_output_format(OutputFormat::Json, Vec::new());
@@ -263,7 +327,7 @@ fn print_card(
         _ => panic!(),
     };
 
-    execute!(stdout, cursor::MoveTo(0, 1))?;
+    execute!(stdout, cursor::MoveTo(0, row))?;
     execute!(stdout, Clear(ClearType::FromCursorDown))?;
     write!(
         stdout,
@@ -279,8 +343,8 @@ fn print_card(
 
 // TODO: get rid of comments in this version too
 #[cfg(feature = "pandoc")]
-fn stringify_pandoc_block<'a>(
-    block: &'a pandoc_types::definition::Block,
+fn stringify_pandoc_block(
+    block: &pandoc_types::definition::Block,
 ) -> Result<String, Box<dyn Error>> {
     use crossterm::style::Color;
     use pandoc_types::definition::{Block, ListNumberDelim, ListNumberStyle};
@@ -363,10 +427,10 @@ fn stringify_pandoc_block<'a>(
                 | ListNumberStyle::Decimal => |n| format!("{}", n),
                 // TODO: investigate how pandoc handles lengths greater than 26
                 ListNumberStyle::LowerAlpha => {
-                    |n| String::from_utf8_lossy(&[97 + (n - 1 % 26) as u8]).to_string()
+                    |n| String::from_utf8_lossy(&[97 + (n - 1) as u8]).to_string()
                 }
                 ListNumberStyle::UpperAlpha => {
-                    |n| String::from_utf8_lossy(&[65 + (n - 1 % 26) as u8]).to_string()
+                    |n| String::from_utf8_lossy(&[65 + (n - 1) as u8]).to_string()
                 }
                 ListNumberStyle::LowerRoman => |n| Roman::from(n as u32).unwrap().to_lowercase(),
                 ListNumberStyle::UpperRoman => |n| Roman::from(n as u32).unwrap().to_string(),
@@ -432,7 +496,7 @@ fn stringify_pandoc_block<'a>(
             .collect::<Result<String, _>>(),
         Block::Header(lvl, _, inline) => Ok(format!(
             "{} {}",
-            "#".repeat(*lvl as usize).bold().to_string(),
+            "#".repeat(*lvl as usize).bold(),
             inline
                 .iter()
                 .map(stringify_pandoc_inline)
@@ -459,31 +523,25 @@ fn stringify_pandoc_block<'a>(
                         .collect::<Result<Vec<String>, _>>()
                 })
                 .collect::<Result<Vec<Vec<String>>, _>>()?;
-            for row in table
-                .bodies
-                .iter()
-                .map(|body| {
-                    body.body
-                        .iter()
-                        .map(|row| {
-                            row.cells
-                                .iter()
-                                .map(|cell| {
-                                    cell.content
-                                        .iter()
-                                        .map(stringify_pandoc_block)
-                                        .collect::<Result<String, _>>()
-                                })
-                                .collect::<Result<Vec<String>, _>>()
-                        })
-                        .flatten()
-                        .collect::<Vec<Vec<String>>>()
-                })
-                .flatten()
-            {
+            for row in table.bodies.iter().flat_map(|body| {
+                body.body
+                    .iter()
+                    .flat_map(|row| {
+                        row.cells
+                            .iter()
+                            .map(|cell| {
+                                cell.content
+                                    .iter()
+                                    .map(stringify_pandoc_block)
+                                    .collect::<Result<String, _>>()
+                            })
+                            .collect::<Result<Vec<String>, _>>()
+                    })
+                    .collect::<Vec<Vec<String>>>()
+            }) {
                 rows.push(row);
             }
-            let mut col_widths = Vec::from_iter(std::iter::repeat(0).take(rows[0].len()));
+            let mut col_widths = Vec::from_iter(std::iter::repeat_n(0, rows[0].len()));
             for row in &rows {
                 for (i, col) in row.iter().enumerate() {
                     if col_widths[i] < col.chars().count() {
@@ -524,8 +582,8 @@ fn stringify_pandoc_block<'a>(
 }
 
 #[cfg(feature = "pandoc")]
-fn stringify_pandoc_inline<'a>(
-    block: &'a pandoc_types::definition::Inline,
+fn stringify_pandoc_inline(
+    block: &pandoc_types::definition::Inline,
 ) -> Result<String, Box<dyn Error>> {
     use crossterm::style::Attribute;
     use pandoc_types::definition::{Inline, QuoteType};
@@ -572,10 +630,10 @@ fn stringify_pandoc_inline<'a>(
             .collect::<Result<String, _>>()
             .map(|s| {
                 s.chars()
-                    .map(|c| {
-                        if c >= 'a' && c <= 'z' {
+                    .map(|c: char| {
+                        if c.is_ascii_lowercase() {
                             converters::SMALL_CAPS[(c as u32 - 'a' as u32) as usize]
-                        } else if c >= 'A' && c <= 'Z' {
+                        } else if c.is_ascii_uppercase() {
                             converters::SMALL_CAPS[(c as u32 - 'A' as u32) as usize]
                         } else {
                             c
@@ -632,20 +690,15 @@ fn stringify_pandoc_inline<'a>(
 // TODO: add alternate implementation using configurable pandoc feature here with prettier printing
 // (https://crates.io/search?q=pandoc)
 #[cfg(not(feature = "pandoc"))]
-fn print_card(
-    stdout: &mut io::Stdout,
-    component: usize,
-    components: &Vec<String>,
-) -> Result<(), io::Error> {
+fn print_card(stdout: &mut io::Stdout, face: &str, row: u16) -> Result<(), io::Error> {
     use textwrap::{wrap, Options};
 
-    execute!(stdout, cursor::MoveTo(0, 1))?;
+    execute!(stdout, cursor::MoveTo(0, row))?;
     execute!(stdout, Clear(ClearType::FromCursorDown))?;
     lazy_static! {
         static ref RE: Regex = Regex::new("<!--([^-]|-[^-]|--[^>])*-->\n{0,2}").unwrap();
     }
-    let joined = components[..component + 1].join("\n---\n");
-    let raw = RE.replace_all(&joined, "");
+    let raw = RE.replace_all(face, "");
     let lines: String = raw
         .split("\n")
         .map(|line| wrap(line, Options::with_termwidth()).join("\r\n"))
@@ -654,6 +707,307 @@ fn print_card(
     write!(stdout, "{}", lines)
 }
 
+/// Returns a hint such as `, press space to mark as remembered`, naming the keys bound to
+/// `command`, or nothing if there are none.
+fn key_hint(bindings: &Bindings, command: Command, action: &str) -> String {
+    let keys = bindings
+        .keys(command)
+        .iter()
+        .map(|key| key.to_string())
+        .collect::<Vec<String>>();
+    if keys.is_empty() {
+        String::new()
+    } else {
+        format!(", press {} to {}", keys.join(" or "), action)
+    }
+}
+
+fn print_answer(
+    stdout: &mut io::Stdout,
+    answer: &Answer,
+    bindings: &Bindings,
+) -> Result<(), Box<dyn Error>> {
+    match answer {
+        Answer::Untyped => {}
+        Answer::Typing(input) => write!(stdout, "\r\n\r\n{} {}█", ">".bold(), input)?,
+        Answer::Typed(typed, expected) => {
+            write!(
+                stdout,
+                "\r\n\r\n{} {}\r\n",
+                ">".bold(),
+                diff::render(&diff::diff(typed, expected))
+            )?;
+            if diff::matches(typed, expected) {
+                let hint = key_hint(bindings, Command::Continue, "mark as remembered");
+                write!(stdout, "{}", format!("Correct{}", hint).green())?
+            } else {
+                let hint = key_hint(bindings, Command::Forget, "mark as forgotten");
+                write!(stdout, "{}", format!("Incorrect{}", hint).red())?
+            }
+        }
+        Answer::Choosing(choices) => {
+            write!(stdout, "\r\n")?;
+            for (i, choice) in choices.iter().enumerate() {
+                let marker = if choice.selected { "[x]" } else { "[ ]" };
+                write!(stdout, "\r\n{} {} {}", i + 1, marker, choice.text)?;
+            }
+        }
+        Answer::Chosen(choices) => {
+            write!(stdout, "\r\n")?;
+            for (i, choice) in choices.iter().enumerate() {
+                let marker = if choice.selected { "[x]" } else { "[ ]" };
+                let line = format!("{} {} {}", i + 1, marker, choice.text);
+                if choice.correct {
+                    write!(stdout, "\r\n{}", line.green())?;
+                } else if choice.selected {
+                    write!(stdout, "\r\n{}", line.red())?;
+                } else {
+                    write!(stdout, "\r\n{}", line)?;
+                }
+            }
+            if chose_correctly(choices) {
+                let hint = key_hint(bindings, Command::Continue, "mark as remembered");
+                write!(stdout, "\r\n\r\n{}", format!("Correct{}", hint).green())?
+            } else {
+                // continuing after a wrong choice marks the card as forgotten
+                let hint = key_hint(bindings, Command::Continue, "mark as forgotten");
+                write!(stdout, "\r\n\r\n{}", format!("Incorrect{}", hint).red())?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses durations such as `90s`, `15m` or `1h30m`.
+fn parse_duration(duration: &str) -> Result<Duration, ValueError> {
+    lazy_static! {
+        static ref PART: Regex = Regex::new(r"(\d+)([hms])").unwrap();
+    }
+
+    let invalid = || ValueError::from(format!("invalid duration `{}`", duration));
+    if duration.is_empty() || !PART.replace_all(duration, "").is_empty() {
+        return Err(invalid());
+    }
+    let mut seconds = 0;
+    for captures in PART.captures_iter(duration) {
+        let n = captures[1].parse::<u64>().map_err(|_| invalid())?;
+        seconds += match &captures[2] {
+            "h" => n * 60 * 60,
+            "m" => n * 60,
+            _ => n,
+        };
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Returns the delays after which forgotten cards are shown again during a session, as set by
+/// `learning_steps` in a config, which defaults to a single step of ten minutes.
+fn learning_steps(config: &Mapping, path: &Path) -> Result<Vec<Duration>, Box<dyn Error>> {
+    let invalid = || {
+        ValueError::from(format!(
+            "`learning_steps` key was not a list of durations in {}",
+            path.display()
+        ))
+    };
+    match config.get(&Value::String("learning_steps".to_string())) {
+        None | Some(Value::Null) => Ok(vec![Duration::from_secs(10 * 60)]),
+        Some(Value::Sequence(steps)) => steps
+            .iter()
+            .map(|step| match step {
+                Value::String(step) => Ok(parse_duration(step)?),
+                _ => Err(Box::new(invalid()) as Box<dyn Error>),
+            })
+            .collect(),
+        Some(_) => Err(Box::new(invalid())),
+    }
+}
+
+/// Reads how cards are skipped from a config: `skip_after_reveal` is one of `allow` (the default),
+/// `disallow` or `peek`, and `skip_defer` is the number of cards that come before a skipped one,
+/// which otherwise goes to the end of the queue.
+fn skip(config: &Mapping, path: &Path) -> Result<Skip, Box<dyn Error>> {
+    let after_reveal = match config.get(&Value::String("skip_after_reveal".to_string())) {
+        None | Some(Value::Null) => SkipAfterReveal::Allow,
+        Some(Value::String(s)) if s == "allow" => SkipAfterReveal::Allow,
+        Some(Value::String(s)) if s == "disallow" => SkipAfterReveal::Disallow,
+        Some(Value::String(s)) if s == "peek" => SkipAfterReveal::Peek,
+        Some(_) => {
+            return Err(Box::new(ValueError::from(format!(
+                "`skip_after_reveal` key was not one of allow, disallow or peek in {}",
+                path.display()
+            ))))
+        }
+    };
+    let defer = match config.get(&Value::String("skip_defer".to_string())) {
+        None | Some(Value::Null) => None,
+        Some(Value::Number(n)) if n.as_u64().map(|n| n >= 1).unwrap_or(false) => {
+            n.as_u64().map(|n| n as usize)
+        }
+        Some(_) => {
+            return Err(Box::new(ValueError::from(format!(
+                "`skip_defer` key was not a positive integer in {}",
+                path.display()
+            ))))
+        }
+    };
+    Ok(Skip {
+        after_reveal,
+        defer,
+    })
+}
+
+/// Prints the current face of the session's card along with its answer, warning if the card is a
+/// leech so that it can be rewritten or archived rather than failed again. With `header`, the card
+/// is preceded by a line showing where it is and how it has been reviewed. Hints about grading
+/// name the keys in `bindings`.
+fn print_session(
+    stdout: &mut io::Stdout,
+    session: &ReviewSession,
+    header: bool,
+    bindings: &Bindings,
+) -> Result<(), Box<dyn Error>> {
+    match (header, session.card(), session.state()) {
+        (true, Some(card), Some(state)) => {
+            execute!(stdout, cursor::MoveTo(0, 1))?;
+            execute!(stdout, Clear(ClearType::FromCursorDown))?;
+            let mut details = vec![card.to_string()];
+            if !state.tags.is_empty() {
+                details.push(format!("tags: {}", state.tags.join(", ")));
+            }
+            match state.last_review {
+                Some(last_review) => {
+                    details.push(format!(
+                        "{} review{}",
+                        state.reviews,
+                        if state.reviews == 1 { "" } else { "s" }
+                    ));
+                    details.push(format!("last {}", last_review.format(DATE_FORMAT)));
+                    details.push(format!("interval {}d", state.interval));
+                }
+                None => details.push("new".to_string()),
+            }
+            let (cols, _) = size()?;
+            let line = details.join(" · ");
+            write!(
+                stdout,
+                "{}",
+                line.chars().take(cols as usize).collect::<String>().dim()
+            )?;
+            print_card(stdout, session.face(), 3)?;
+        }
+        _ => print_card(stdout, session.face(), 1)?,
+    }
+    print_answer(stdout, session.answer(), bindings)?;
+    if let Some(lapses) = session.leech_lapses() {
+        write!(
+            stdout,
+            "\r\n\r\n{}",
+            format!(
+                "Leech: forgotten on {} days, consider rewriting or archiving it",
+                lapses
+            )
+            .yellow()
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints how long the session has been going on for in the bottom right corner, along with the
+/// time limit if there is one.
+fn print_elapsed(
+    stdout: &mut io::Stdout,
+    elapsed: Duration,
+    limit: Option<Duration>,
+) -> Result<(), Box<dyn Error>> {
+    let text = match limit {
+        Some(limit) => format!("{} / {}", format_duration(elapsed), format_duration(limit)),
+        None => format_duration(elapsed),
+    };
+    let (cols, rows) = size()?;
+    execute!(
+        stdout,
+        cursor::MoveTo(
+            cols.saturating_sub(text.len() as u16),
+            rows.saturating_sub(1)
+        )
+    )?;
+    write!(stdout, "{}", text.dim())?;
+    Ok(())
+}
+
+/// Prints `notice` in the bottom left corner, where it stays until the card is shown again.
+fn print_notice(stdout: &mut io::Stdout, notice: &str) -> Result<(), Box<dyn Error>> {
+    let (_, rows) = size()?;
+    execute!(stdout, cursor::MoveTo(0, rows.saturating_sub(1)))?;
+    write!(stdout, "{}", notice.yellow())?;
+    Ok(())
+}
+
+/// Formats durations as `m:ss`, or `h:mm:ss` from an hour on.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 60 * 60 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / (60 * 60),
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Returns the action for a key press that's part of answering the card, when the answer is being
+/// typed or chosen.
+fn answer_action(answer: &Answer, key: KeyEvent) -> Option<Action> {
+    match (answer, key.code) {
+        (Answer::Typing(_), KeyCode::Char(c)) => Some(Action::Type(c)),
+        (Answer::Typing(_), KeyCode::Backspace) => Some(Action::Backspace),
+        (Answer::Typing(_) | Answer::Choosing(_), KeyCode::Enter) => Some(Action::Submit),
+        (Answer::Typing(_), KeyCode::Esc) => Some(Action::Cancel),
+        // options are numbered from 1, so only the first nine can be selected
+        (Answer::Choosing(_), KeyCode::Char(c @ '1'..='9')) => {
+            Some(Action::Choose(c as usize - '1' as usize))
+        }
+        _ => None,
+    }
+}
+
+/// Prints the keys bound to each command in place of the card.
+fn print_help(stdout: &mut io::Stdout, bindings: &Bindings) -> Result<(), Box<dyn Error>> {
+    execute!(stdout, cursor::MoveTo(0, 1))?;
+    execute!(stdout, Clear(ClearType::FromCursorDown))?;
+    let rows = Command::ALL
+        .iter()
+        .map(|command| {
+            let keys = bindings
+                .keys(*command)
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<String>>()
+                .join(", ");
+            (keys, command.description())
+        })
+        .collect::<Vec<(String, &str)>>();
+    // Can be unwrapped safely because there is a row for every command
+    let width = rows
+        .iter()
+        .map(|(keys, _)| keys.chars().count())
+        .max()
+        .unwrap();
+    for (keys, description) in rows {
+        write!(
+            stdout,
+            "\r\n{}  {}",
+            format!("{:width$}", keys, width = width).bold(),
+            description
+        )?;
+    }
+    write!(stdout, "\r\n\r\n{}", "Press any key to continue".dim())?;
+    Ok(())
+}
+
 // TODO: turn this progress bar into its own crate with support for non-tui applications as well as
 // different progress alignment and support for multiple data types such as time and storage units.
 // if we want to get real fancy, we could support animations so it's even smoother
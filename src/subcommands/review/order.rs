@@ -0,0 +1,105 @@
+use chrono::Utc;
+use rand::{seq::SliceRandom, thread_rng};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::entities::algorithms;
+use crate::entities::cards::{self, Card};
+
+/// Orders `cards` for review according to `strategy`, which is one of:
+///
+/// - `random`: shuffles all the cards
+/// - `overdue-first`: sorts cards by their due date under `algorithm`, shuffling those due on the
+///   same day
+/// - `folder-grouped-shuffle`: keeps the cards of each folder together, shuffling the folders
+///   and the cards within them
+/// - `new-last`: shuffles the cards, but puts those that have never been reviewed last
+/// - `interleaved-by-deck`: shuffles the cards of each folder and takes one from each folder in
+///   turn
+/// - `walk`: keeps the order the cards were found in
+///
+/// Except with `walk`, cards that were already reviewed today come after the others.
+pub fn order(
+    mut cards: Vec<Card>,
+    strategy: &str,
+    algorithm: &str,
+) -> Result<Vec<Card>, Box<dyn Error>> {
+    if strategy == "walk" {
+        return Ok(cards);
+    }
+
+    cards.shuffle(&mut thread_rng());
+    cards = match strategy {
+        "random" => cards,
+        "overdue-first" => {
+            let mut dated = Vec::new();
+            for card in cards {
+                let due = algorithms::due_date(algorithm, &cards::read_review_history(&card)?);
+                dated.push((due, card));
+            }
+            dated.sort_by_key(|(due, _)| *due);
+            dated.into_iter().map(|(_, card)| card).collect()
+        }
+        "folder-grouped-shuffle" => {
+            let mut folders = group_by_folder(cards);
+            folders.shuffle(&mut thread_rng());
+            folders.into_iter().flatten().collect()
+        }
+        "new-last" => {
+            let mut keyed = Vec::new();
+            for card in cards {
+                let new = cards::read_review_history(&card)?.is_empty();
+                keyed.push((new, card));
+            }
+            keyed.sort_by_key(|(new, _)| *new);
+            keyed.into_iter().map(|(_, card)| card).collect()
+        }
+        "interleaved-by-deck" => {
+            let mut folders = group_by_folder(cards);
+            folders.shuffle(&mut thread_rng());
+            let mut folders = folders
+                .into_iter()
+                .map(|folder| folder.into_iter())
+                .collect::<Vec<_>>();
+            let mut interleaved = Vec::new();
+            while !folders.is_empty() {
+                folders.retain_mut(|folder| match folder.next() {
+                    Some(card) => {
+                        interleaved.push(card);
+                        true
+                    }
+                    None => false,
+                });
+            }
+            interleaved
+        }
+        _ => panic!(), // Cannot occur because clap will block invalid order arguments
+    };
+
+    let today = Utc::today();
+    let mut keyed = Vec::new();
+    for card in cards {
+        let seen = cards::read_review_history(&card)?
+            .last()
+            .map(|review| review.date == today)
+            .unwrap_or(false);
+        keyed.push((seen, card));
+    }
+    keyed.sort_by_key(|(seen, _)| *seen);
+    Ok(keyed.into_iter().map(|(_, card)| card).collect())
+}
+
+/// Splits `cards` by the folder they're in, keeping their order within each folder.
+fn group_by_folder(cards: Vec<Card>) -> Vec<Vec<Card>> {
+    let mut folders = BTreeMap::<PathBuf, Vec<Card>>::new();
+    for card in cards {
+        let folder = card
+            .path()
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
+        folders.entry(folder).or_default().push(card);
+    }
+    folders.into_values().collect()
+}
@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::entities::cards::Card;
+
+/// Where a card is within a `Queue`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Slot {
+    Pending,
+    /// Forgotten during the session and waiting for the given learning step to pass.
+    Relearning(usize),
+    Done,
+    /// Hidden for the rest of the session, such as when a sibling was answered or the card was
+    /// archived.
+    Buried,
+}
+
+/// A card leaving the front of its slot, recorded so that it can be undone.
+struct Move {
+    from: Slot,
+    /// When the card was due again, if it came from a relearning slot.
+    due: Option<Instant>,
+    to: Slot,
+    /// Where the card was put among the pending cards, if it was moved there.
+    index: usize,
+    /// Where the siblings that were buried along with this move were among the pending cards, in
+    /// ascending order, counting the card that had just been made current as the first of them.
+    buried: Vec<usize>,
+}
+
+/// The cards of a review session.
+///
+/// Cards start out pending, and once they're answered they either move to done, or, if they were
+/// forgotten, to relearning, from which they come back once the first learning step has passed.
+/// Each time a relearning card is remembered it moves to the next step, until it has gone through
+/// all of them and is done. Cards that shouldn't be seen again during the session are buried.
+///
+/// Apart from burying siblings and deferring cards, all operations take constant time: the
+/// relearning cards of each step share the same delay, so keeping one queue per step keeps each of
+/// them ordered by due time. Times are passed in rather than read so that sessions can be replayed.
+pub struct Queue {
+    pending: VecDeque<Card>,
+    relearning: Vec<VecDeque<(Instant, Card)>>,
+    done: Vec<Card>,
+    buried: Vec<Card>,
+    current: Option<(Slot, Option<Instant>, Card)>,
+    steps: Vec<Duration>,
+    moves: Vec<Move>,
+}
+
+impl Queue {
+    /// Creates a queue of `cards` in the given order, with `steps` being the delays after which
+    /// forgotten cards are shown again. Forgotten cards come back right away if there are no steps.
+    pub fn new(cards: Vec<Card>, mut steps: Vec<Duration>, now: Instant) -> Queue {
+        if steps.is_empty() {
+            steps.push(Duration::ZERO);
+        }
+        let mut queue = Queue {
+            pending: cards.into(),
+            relearning: steps.iter().map(|_| VecDeque::new()).collect(),
+            done: Vec::new(),
+            buried: Vec::new(),
+            current: None,
+            steps,
+            moves: Vec::new(),
+        };
+        queue.advance(now);
+        queue
+    }
+
+    /// Returns the card being reviewed, which is `None` once the session is over.
+    pub fn current(&self) -> Option<&Card> {
+        self.current.as_ref().map(|(_, _, card)| card)
+    }
+
+    /// Returns the number of cards left to review, including the current one.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+            + self.relearning.iter().map(VecDeque::len).sum::<usize>()
+            + self.current.iter().count()
+    }
+
+    /// Returns the cards that have been answered for good during the session.
+    pub fn done(&self) -> &[Card] {
+        &self.done
+    }
+
+    /// Moves the current card on after it was remembered.
+    pub fn remember(&mut self, now: Instant) {
+        let to = match self.current {
+            Some((Slot::Relearning(step), _, _)) if step + 1 < self.steps.len() => {
+                Slot::Relearning(step + 1)
+            }
+            _ => Slot::Done,
+        };
+        self.move_current(to, now);
+    }
+
+    /// Moves the current card back to the first learning step after it was forgotten.
+    pub fn forget(&mut self, now: Instant) {
+        self.move_current(Slot::Relearning(0), now);
+    }
+
+    /// Moves the current card back among the pending cards, so that `by` of them come before it,
+    /// or to the end if `by` is `None` or there are fewer of them.
+    pub fn defer(&mut self, by: Option<usize>, now: Instant) {
+        let (from, due, card) = match self.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        let index = by.map_or(self.pending.len(), |by| by.min(self.pending.len()));
+        self.pending.insert(index, card);
+        self.moves.push(Move {
+            from,
+            due,
+            to: Slot::Pending,
+            index,
+            buried: Vec::new(),
+        });
+        self.advance(now);
+    }
+
+    /// Hides the current card for the rest of the session.
+    pub fn bury(&mut self, now: Instant) {
+        self.move_current(Slot::Buried, now);
+    }
+
+    /// Buries the pending cards for which `is_sibling` returns true, along with the last move, so
+    /// that undoing it puts them back where they were. This is meant to be called once, right after
+    /// answering or archiving a card.
+    pub fn bury_siblings<E>(
+        &mut self,
+        now: Instant,
+        mut is_sibling: impl FnMut(&Card) -> Result<bool, E>,
+    ) -> Result<(), E> {
+        let mut buried = Vec::new();
+
+        // the card after the answered one was already taken from the front of the pending cards
+        let mut offset = 0;
+        if let Some((Slot::Pending, _, card)) = &self.current {
+            offset = 1;
+            if is_sibling(card)? {
+                // Can be unwrapped safely because the current card was just checked
+                let (_, _, card) = self.current.take().unwrap();
+                self.buried.push(card);
+                buried.push(0);
+            }
+        }
+
+        let mut kept = VecDeque::with_capacity(self.pending.len());
+        for (i, card) in self.pending.drain(..).enumerate() {
+            if is_sibling(&card)? {
+                self.buried.push(card);
+                buried.push(i + offset);
+            } else {
+                kept.push_back(card);
+            }
+        }
+        self.pending = kept;
+        if let Some(last) = self.moves.last_mut() {
+            last.buried = buried;
+        }
+        self.advance(now);
+        Ok(())
+    }
+
+    /// Reverts the last move, making the card that was moved current again and returning it.
+    pub fn undo(&mut self) -> Option<&Card> {
+        let last = self.moves.pop()?;
+
+        // the card that was about to be reviewed goes back to the front of where it came from
+        if let Some((from, due, card)) = self.current.take() {
+            match (from, due) {
+                (Slot::Relearning(step), Some(due)) => {
+                    self.relearning[step].push_front((due, card))
+                }
+                _ => self.pending.push_front(card),
+            }
+        }
+
+        // with the current card back in front, the pending cards are as they were when the siblings
+        // were buried, so inserting them in order puts each back at its original index
+        let siblings = self.buried.split_off(self.buried.len() - last.buried.len());
+        for (index, card) in last.buried.into_iter().zip(siblings) {
+            self.pending.insert(index, card);
+        }
+        let card = match last.to {
+            Slot::Pending => self.pending.remove(last.index),
+            Slot::Relearning(step) => self.relearning[step].pop_back().map(|(_, card)| card),
+            Slot::Done => self.done.pop(),
+            Slot::Buried => self.buried.pop(),
+        }
+        .unwrap(); // Can be unwrapped safely because later moves have all been undone already
+        self.current = Some((last.from, last.due, card));
+        self.current()
+    }
+
+    fn move_current(&mut self, to: Slot, now: Instant) {
+        let (from, due, card) = match self.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        let index = self.pending.len();
+        match to {
+            Slot::Pending => self.pending.push_back(card),
+            Slot::Relearning(step) => {
+                self.relearning[step].push_back((now + self.steps[step], card))
+            }
+            Slot::Done => self.done.push(card),
+            Slot::Buried => self.buried.push(card),
+        }
+        self.moves.push(Move {
+            from,
+            due,
+            to,
+            index,
+            buried: Vec::new(),
+        });
+        self.advance(now);
+    }
+
+    /// Makes the next card current: the relearning card that has been due the longest, or if none
+    /// are due yet, the next pending card, or if there are none, the relearning card due soonest.
+    fn advance(&mut self, now: Instant) {
+        if self.current.is_some() {
+            return;
+        }
+        let soonest = (0..self.relearning.len())
+            .filter_map(|step| self.relearning[step].front().map(|(due, _)| (*due, step)))
+            .min();
+        self.current = match soonest {
+            Some((due, step)) if due <= now || self.pending.is_empty() => self.relearning[step]
+                .pop_front()
+                .map(|(due, card)| (Slot::Relearning(step), Some(due), card)),
+            _ => self
+                .pending
+                .pop_front()
+                .map(|card| (Slot::Pending, None, card)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::cards;
+    use std::fs;
+
+    const STEP: Duration = Duration::from_secs(600);
+
+    /// Returns one card per name, from a headings file in a directory of its own. The directory is
+    /// removed again right away, since queues never read the files of their cards.
+    fn deck(test: &str, names: &[&str]) -> Vec<Card> {
+        let dir =
+            std::env::temp_dir().join(format!("spaced-queue-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("deck.md");
+        let sections = names
+            .iter()
+            .map(|name| format!("## {}\n\nanswer\n", name))
+            .collect::<String>();
+        fs::write(&path, format!("---\nformat: headings\n---\n\n{}", sections)).unwrap();
+        let cards = cards::file_cards(&path).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        cards
+    }
+
+    fn key(card: Option<&Card>) -> Option<String> {
+        card.and_then(Card::key)
+    }
+
+    /// Remembers cards until the queue is empty, returning them in the order they were seen.
+    fn remember_all(queue: &mut Queue, now: Instant) -> Vec<String> {
+        let mut seen = Vec::new();
+        while let Some(card) = queue.current() {
+            seen.push(card.key().unwrap());
+            queue.remember(now);
+        }
+        seen
+    }
+
+    #[test]
+    fn remember_moves_cards_to_done() {
+        let now = Instant::now();
+        let mut queue = Queue::new(deck("remember", &["a", "b"]), vec![STEP], now);
+        assert_eq!(queue.len(), 2);
+        queue.remember(now);
+        assert_eq!(key(queue.current()), Some("b".to_string()));
+        assert_eq!(queue.len(), 1);
+        queue.remember(now);
+        assert_eq!(queue.current(), None);
+        assert_eq!(queue.done().len(), 2);
+    }
+
+    #[test]
+    fn forgotten_cards_come_back_after_each_step() {
+        let now = Instant::now();
+        let steps = vec![STEP, STEP * 2];
+        let mut queue = Queue::new(deck("forget", &["a", "b", "c"]), steps, now);
+        queue.forget(now);
+        assert_eq!(key(queue.current()), Some("b".to_string()));
+        assert_eq!(queue.len(), 3);
+
+        // the forgotten card waits for its step while there are pending cards
+        queue.remember(now);
+        assert_eq!(key(queue.current()), Some("c".to_string()));
+
+        // and comes back once it's due
+        queue.remember(now + STEP);
+        assert_eq!(key(queue.current()), Some("a".to_string()));
+        queue.remember(now + STEP);
+        assert_eq!(key(queue.current()), Some("a".to_string()));
+        queue.remember(now + STEP * 3);
+        assert_eq!(queue.current(), None);
+        assert_eq!(queue.done().len(), 3);
+    }
+
+    #[test]
+    fn defer_moves_cards_back() {
+        let now = Instant::now();
+        let mut queue = Queue::new(deck("defer", &["a", "b", "c", "d"]), vec![STEP], now);
+        queue.defer(Some(1), now);
+        assert_eq!(key(queue.current()), Some("b".to_string()));
+        queue.defer(None, now);
+        assert_eq!(remember_all(&mut queue, now), ["a", "c", "d", "b"]);
+    }
+
+    #[test]
+    fn undo_restores_buried_siblings_in_place() {
+        let now = Instant::now();
+        let names = ["a", "b", "c", "d", "e"];
+        let mut queue = Queue::new(deck("bury-siblings", &names), vec![STEP], now);
+        queue.remember(now);
+        let siblings = ["b", "d"];
+        queue
+            .bury_siblings(now, |card| {
+                Ok::<_, ()>(siblings.contains(&card.key().unwrap().as_str()))
+            })
+            .unwrap();
+        assert_eq!(key(queue.current()), Some("c".to_string()));
+        assert_eq!(queue.len(), 2);
+
+        assert_eq!(key(queue.undo()), Some("a".to_string()));
+        assert_eq!(queue.len(), 5);
+        assert_eq!(remember_all(&mut queue, now), names);
+    }
+
+    #[test]
+    fn undo_reverts_every_kind_of_move() {
+        let now = Instant::now();
+        let names = ["a", "b", "c", "d"];
+        let mut queue = Queue::new(deck("undo", &names), vec![STEP], now);
+        queue.forget(now);
+        queue.defer(Some(1), now);
+        queue.bury(now);
+        queue.remember(now);
+        queue.bury_siblings(now, |_| Ok::<_, ()>(true)).unwrap();
+        assert_eq!(queue.len(), 1);
+
+        assert_eq!(key(queue.undo()), Some("b".to_string()));
+        assert_eq!(queue.len(), 3);
+        assert_eq!(key(queue.undo()), Some("c".to_string()));
+        assert_eq!(key(queue.undo()), Some("b".to_string()));
+        assert_eq!(key(queue.undo()), Some("a".to_string()));
+        assert!(queue.undo().is_none());
+        assert_eq!(queue.done().len(), 0);
+        assert_eq!(remember_all(&mut queue, now), names);
+    }
+}
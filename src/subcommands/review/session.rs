@@ -0,0 +1,643 @@
+use chrono::Utc;
+use rand::{seq::SliceRandom, thread_rng};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use super::queue::Queue;
+use super::summary::Graded;
+use crate::entities::cards::{self, Card, CardState, LeechAction};
+use crate::entities::history::Review;
+use crate::entities::journal::{self, Entry, Event as JournalEvent};
+
+/// Something done to a session, usually in response to a key press.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Shows the next face of the card, or once they've all been shown, marks it as remembered.
+    /// For multiple choice cards that have been graded, the card is marked according to the grade.
+    Continue,
+    Forget,
+    Skip,
+    /// Archives the card's file, which excludes it from future reviews, and hides the file's other
+    /// cards for the rest of the session.
+    Archive,
+    /// Suspends the card until tomorrow, leaving the other cards in its file alone.
+    Bury,
+    Undo,
+    /// Re-reads the current card, such as after it has been edited.
+    Reload,
+    /// Adds a character to the answer being typed.
+    Type(char),
+    /// Removes the last character from the answer being typed.
+    Backspace,
+    /// Submits the typed answer or the selected choices.
+    Submit,
+    /// Stops typing the answer, so that the card can be reviewed normally.
+    Cancel,
+    /// Toggles the selection of a multiple choice option, counting from 0.
+    Choose(usize),
+}
+
+/// What an action did to a session, which tells frontends what to show again.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// The face or the answer of the current card changed.
+    CardUpdated,
+    /// Another card is being reviewed, and the progress changed.
+    CardChanged,
+    /// Every card has been reviewed, or the time ran out.
+    Finished,
+    /// The card wasn't skipped, because skipping isn't allowed once its answer has been shown.
+    SkipDisallowed,
+    /// The last action couldn't be undone for the given reason, such as its card having been
+    /// edited since, so nothing changed.
+    UndoFailed(String),
+}
+
+/// What skipping does once the answer of the current card has been shown.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SkipAfterReveal {
+    Allow,
+    Disallow,
+    /// Skipping is allowed, but recorded in the card's history as a peek.
+    Peek,
+}
+
+/// How cards are skipped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Skip {
+    pub after_reveal: SkipAfterReveal,
+    /// The number of cards that come before a skipped one, or `None` to move it to the end.
+    pub defer: Option<usize>,
+}
+
+/// The answer given for the current card, when answers are typed or chosen before being revealed.
+pub enum Answer {
+    Untyped,
+    Typing(String),
+    /// The typed answer and the expected one.
+    Typed(String, String),
+    /// The shuffled options of a multiple choice card while they are being selected.
+    Choosing(Vec<Choice>),
+    /// The options of a multiple choice card once the selection has been submitted.
+    Chosen(Vec<Choice>),
+}
+
+#[derive(Clone)]
+pub struct Choice {
+    pub text: String,
+    pub correct: bool,
+    pub selected: bool,
+}
+
+impl Answer {
+    fn for_card(card: &Card, type_answer: bool) -> Result<Answer, Box<dyn Error>> {
+        let mut choices = card
+            .choices()?
+            .into_iter()
+            .map(|(text, correct)| Choice {
+                text,
+                correct,
+                selected: false,
+            })
+            .collect::<Vec<Choice>>();
+        if !choices.is_empty() {
+            choices.shuffle(&mut thread_rng());
+            Ok(Answer::Choosing(choices))
+        } else if type_answer || card.type_answer()? {
+            Ok(Answer::Typing(String::new()))
+        } else {
+            Ok(Answer::Untyped)
+        }
+    }
+}
+
+pub fn chose_correctly(choices: &[Choice]) -> bool {
+    choices
+        .iter()
+        .all(|choice| choice.selected == choice.correct)
+}
+
+/// An action that can be undone. Actions that changed the card's file are journaled, and the
+/// journal entry is what's needed to revert them. Where the card went within the session is
+/// undone by the queue.
+enum UndoItem {
+    Journaled(Entry),
+    Skip,
+}
+
+/// A review session, independent of how it's displayed.
+///
+/// Frontends show the current card, pass in actions, and use the returned events to tell what
+/// needs to be shown again. Actions that don't apply, such as undoing with nothing to undo, return
+/// no event.
+pub struct ReviewSession {
+    queue: Queue,
+    algorithm: String,
+    type_answer: bool,
+    skip: Skip,
+    deadline: Option<Instant>,
+    forgotten: usize,
+    undo_stack: Vec<UndoItem>,
+    finished: bool,
+    started: Instant,
+    graded: Vec<Graded>,
+
+    // the state of the current card, which is read once when it comes up
+    faces: Vec<String>,
+    component: usize,
+    answer: Answer,
+    state: Option<CardState>,
+    shown_at: Instant,
+    /// When the answer was first shown, which is when the card stopped being recalled.
+    revealed_at: Option<Instant>,
+}
+
+impl ReviewSession {
+    /// Starts a session reviewing `cards` in the given order, with their state read using
+    /// `algorithm`. Forgotten cards come back after each of `learning_steps`, skipped cards are
+    /// moved back according to `skip`, and once `deadline` has passed, the session ends as soon as
+    /// the current card is answered.
+    pub fn new(
+        cards: Vec<Card>,
+        algorithm: &str,
+        learning_steps: Vec<Duration>,
+        type_answer: bool,
+        skip: Skip,
+        deadline: Option<Instant>,
+        now: Instant,
+    ) -> Result<ReviewSession, Box<dyn Error>> {
+        let mut session = ReviewSession {
+            queue: Queue::new(cards, learning_steps, now),
+            algorithm: algorithm.to_string(),
+            type_answer,
+            skip,
+            deadline,
+            forgotten: 0,
+            undo_stack: Vec::new(),
+            finished: false,
+            started: now,
+            graded: Vec::new(),
+            faces: Vec::new(),
+            component: 0,
+            answer: Answer::Untyped,
+            state: None,
+            shown_at: now,
+            revealed_at: None,
+        };
+        session.load(now)?;
+        Ok(session)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the card being reviewed, which is `None` once the session is finished.
+    pub fn card(&self) -> Option<&Card> {
+        self.queue.current()
+    }
+
+    /// Returns the face of the current card that is being shown.
+    pub fn face(&self) -> &str {
+        self.faces.get(self.component).map_or("", String::as_str)
+    }
+
+    pub fn answer(&self) -> &Answer {
+        &self.answer
+    }
+
+    /// Returns the state of the current card as of when it came up.
+    pub fn state(&self) -> Option<&CardState> {
+        self.state.as_ref()
+    }
+
+    /// Returns the number of days on which the current card has been forgotten if it's a leech.
+    pub fn leech_lapses(&self) -> Option<usize> {
+        self.state
+            .as_ref()
+            .filter(|state| state.tags.iter().any(|tag| tag == cards::LEECH_TAG))
+            .map(|state| state.lapses)
+    }
+
+    /// Returns the number of cards that have been remembered for good.
+    pub fn remembered(&self) -> usize {
+        self.queue.done().len()
+    }
+
+    /// Returns the number of times cards have been forgotten.
+    pub fn forgotten(&self) -> usize {
+        self.forgotten
+    }
+
+    /// Returns how long the session has been going on for at the time `now`.
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.started)
+    }
+
+    /// Returns the cards graded during the session, in order.
+    pub fn graded(&self) -> &[Graded] {
+        &self.graded
+    }
+
+    /// Returns the number of cards left to review, including the current one.
+    pub fn remaining(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Applies `action` at the time `now`, returning what changed, if anything.
+    pub fn apply(&mut self, action: Action, now: Instant) -> Result<Option<Event>, Box<dyn Error>> {
+        let card = match self.queue.current() {
+            Some(card) if !self.finished => card.clone(),
+            _ => return Ok(None),
+        };
+        let last = self.faces.len().saturating_sub(1);
+        let revealed = self.revealed();
+
+        match (&mut self.answer, action) {
+            (Answer::Typing(input), Action::Type(c)) => input.push(c),
+            (Answer::Typing(input), Action::Backspace) => {
+                input.pop();
+            }
+            (Answer::Typing(input), Action::Submit) => {
+                let typed = input.clone();
+                self.answer = Answer::Typed(typed, card.answer()?);
+                self.component = last;
+            }
+            (Answer::Typing(_), Action::Cancel) => self.answer = Answer::Untyped,
+            // while typing, keys are part of the answer rather than actions
+            (Answer::Typing(_), _) => return Ok(None),
+            (Answer::Choosing(choices), Action::Choose(i)) if i < choices.len() => {
+                choices[i].selected = !choices[i].selected;
+                // with a single correct option, picking one is the whole attempt
+                if choices.iter().filter(|c| c.correct).count() <= 1 {
+                    self.answer = Answer::Chosen(choices.clone());
+                    self.component = last;
+                }
+            }
+            (Answer::Choosing(choices), Action::Submit) => {
+                self.answer = Answer::Chosen(choices.clone());
+                self.component = last;
+            }
+            // revealing the card would give the answer away
+            (Answer::Choosing(_), Action::Continue) => return Ok(None),
+            // once a multiple choice card has been graded, it moves on with that grade
+            (Answer::Chosen(choices), Action::Continue) if !chose_correctly(choices) => {
+                return self.forget(&card, now)
+            }
+            (_, Action::Continue) if self.component == last => return self.remember(&card, now),
+            (_, Action::Continue) => self.component += 1,
+            (_, Action::Forget) => return self.forget(&card, now),
+            (_, Action::Skip) if revealed => match self.skip.after_reveal {
+                SkipAfterReveal::Allow => return self.defer(now),
+                SkipAfterReveal::Disallow => return Ok(Some(Event::SkipDisallowed)),
+                SkipAfterReveal::Peek => {
+                    cards::mark_peeked(&card, Some(self.answer_ms(now)))?;
+                    self.queue.defer(self.skip.defer, now);
+                    self.journal(&card, JournalEvent::Peeked)?;
+                    return self.next(now);
+                }
+            },
+            (_, Action::Skip) => return self.defer(now),
+            (_, Action::Archive) => {
+                cards::mark_archived(card.path(), true)?;
+                self.queue.bury(now);
+                self.queue
+                    .bury_siblings(now, |other| -> Result<bool, Box<dyn Error>> {
+                        Ok(other.path() == card.path())
+                    })?;
+                self.journal(&card, JournalEvent::Archived)?;
+                return self.next(now);
+            }
+            (_, Action::Bury) => {
+                let suspended_until = cards::read_card_suspended_until(&card)?;
+                let tomorrow = Utc::today().succ();
+                if suspended_until
+                    .map(|until| until < tomorrow)
+                    .unwrap_or(true)
+                {
+                    cards::mark_card_suspended(&card, Some(tomorrow))?;
+                }
+                self.queue.bury(now);
+                self.journal(&card, JournalEvent::Buried(suspended_until))?;
+                return self.next(now);
+            }
+            (_, Action::Undo) => return self.undo(now),
+            (_, Action::Reload) => self.load(now)?,
+            _ => return Ok(None),
+        }
+        if self.revealed_at.is_none() && self.revealed() {
+            self.revealed_at = Some(now);
+        }
+        Ok(Some(Event::CardUpdated))
+    }
+
+    fn remember(&mut self, card: &Card, now: Instant) -> Result<Option<Event>, Box<dyn Error>> {
+        self.grade(card, true, now)?;
+        self.queue.remember(now);
+        self.bury_siblings(card, now)?;
+        self.journal(card, JournalEvent::Remembered)?;
+        self.answered(now)
+    }
+
+    fn forget(&mut self, card: &Card, now: Instant) -> Result<Option<Event>, Box<dyn Error>> {
+        self.forgotten += 1;
+        self.grade(card, false, now)?;
+        let leech_action = cards::mark_leech(card)?;
+        match leech_action {
+            LeechAction::Suspended => self.queue.bury(now),
+            _ => self.queue.forget(now),
+        }
+        self.bury_siblings(card, now)?;
+        self.journal(card, JournalEvent::Forgotten(leech_action))?;
+        self.answered(now)
+    }
+
+    /// Records `event` in the journal, so that it can be undone even after the session is over.
+    fn journal(&mut self, card: &Card, event: JournalEvent) -> Result<(), Box<dyn Error>> {
+        let entry = Entry::new(card, event)?;
+        journal::push(&entry)?;
+        self.undo_stack.push(UndoItem::Journaled(entry));
+        Ok(())
+    }
+
+    /// Returns whether the answer of the current card has been shown.
+    fn revealed(&self) -> bool {
+        self.component > 0 || matches!(self.answer, Answer::Typed(..) | Answer::Chosen(_))
+    }
+
+    /// Returns how long it took to answer the current card: the time from showing it to revealing
+    /// its answer, or to `now` if it's being graded without having been revealed. Time spent
+    /// reading the answer doesn't count, since it says nothing about how well the card is known.
+    fn answer_ms(&self, now: Instant) -> u64 {
+        self.revealed_at
+            .unwrap_or(now)
+            .saturating_duration_since(self.shown_at)
+            .as_millis() as u64
+    }
+
+    /// Moves the current card back in the queue without grading it.
+    fn defer(&mut self, now: Instant) -> Result<Option<Event>, Box<dyn Error>> {
+        self.queue.defer(self.skip.defer, now);
+        self.undo_stack.push(UndoItem::Skip);
+        self.next(now)
+    }
+
+    /// Records a review of `card`, which took as long as it took to reveal its answer.
+    fn grade(&mut self, card: &Card, remembered: bool, now: Instant) -> Result<(), Box<dyn Error>> {
+        let time_ms = self.answer_ms(now);
+        cards::mark(card, remembered, Some(time_ms))?;
+        self.graded.push(Graded {
+            card: card.clone(),
+            review: Review {
+                date: Utc::today(),
+                remembered,
+                time_ms: Some(time_ms),
+                peeked: false,
+            },
+            new: self
+                .state
+                .as_ref()
+                .map(|state| state.reviews == 0)
+                .unwrap_or(false),
+        });
+        Ok(())
+    }
+
+    /// Buries the siblings of `card`, which was just answered, for the rest of the session.
+    fn bury_siblings(&mut self, card: &Card, now: Instant) -> Result<(), Box<dyn Error>> {
+        let group = card.sibling_group()?;
+        self.queue.bury_siblings(now, |other| {
+            Ok(other != card && other.sibling_group()? == group)
+        })
+    }
+
+    /// Moves on after a card was answered, unless the time is up.
+    fn answered(&mut self, now: Instant) -> Result<Option<Event>, Box<dyn Error>> {
+        if self.deadline.map(|d| now >= d).unwrap_or(false) {
+            self.finished = true;
+            return Ok(Some(Event::Finished));
+        }
+        self.next(now)
+    }
+
+    fn undo(&mut self, now: Instant) -> Result<Option<Event>, Box<dyn Error>> {
+        let undo_item = match self.undo_stack.pop() {
+            Some(undo_item) => undo_item,
+            None => return Ok(None),
+        };
+        // the card's file is reverted first, so that the session is left as it was if that fails
+        if let UndoItem::Journaled(entry) = &undo_item {
+            if let Err(e) = journal::revert(entry).and_then(|_| journal::remove(entry)) {
+                self.undo_stack.push(undo_item);
+                return Ok(Some(Event::UndoFailed(e.to_string())));
+            }
+            match entry.event {
+                JournalEvent::Remembered => {
+                    self.graded.pop();
+                }
+                JournalEvent::Forgotten(_) => {
+                    self.graded.pop();
+                    self.forgotten -= 1;
+                }
+                _ => {}
+            }
+        }
+        // Can be unwrapped safely because each undo item was pushed along with a move
+        self.queue.undo().unwrap();
+        self.next(now)
+    }
+
+    fn next(&mut self, now: Instant) -> Result<Option<Event>, Box<dyn Error>> {
+        self.load(now)?;
+        Ok(Some(if self.finished {
+            Event::Finished
+        } else {
+            Event::CardChanged
+        }))
+    }
+
+    /// Reads the current card as it's shown at the time `now`, finishing the session if there are
+    /// none left.
+    fn load(&mut self, now: Instant) -> Result<(), Box<dyn Error>> {
+        let card = match self.queue.current() {
+            Some(card) => card,
+            None => {
+                self.finished = true;
+                return Ok(());
+            }
+        };
+        self.faces = card.faces()?;
+        self.component = 0;
+        self.answer = Answer::for_card(card, self.type_answer)?;
+        self.state = Some(cards::read_state(card, &self.algorithm)?);
+        self.shown_at = now;
+        self.revealed_at = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Held by each test while it uses the journal, which is chosen by an environment variable.
+    static JOURNAL: Mutex<()> = Mutex::new(());
+
+    /// Creates a directory for the cards of `test`, with the journal kept inside it rather than in
+    /// the user's data directory.
+    fn temp_dir(test: &str) -> (MutexGuard<'static, ()>, PathBuf) {
+        let guard = JOURNAL.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = env::temp_dir().join(format!("spaced-session-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        env::set_var("XDG_DATA_HOME", dir.join("data"));
+        (guard, dir)
+    }
+
+    fn reviews(card: &Card) -> usize {
+        cards::read_state(card, "leitner").unwrap().reviews
+    }
+
+    #[test]
+    fn scripted_session() {
+        let (_journal, dir) = temp_dir("scripted");
+        fs::write(
+            dir.join("pair.md"),
+            "---\nreverse: true\n---\nfront\n---\nback\n",
+        )
+        .unwrap();
+        fs::write(dir.join("other.md"), "question\n---\nanswer\n").unwrap();
+        let mut cards = cards::file_cards(&dir.join("pair.md")).unwrap();
+        cards.extend(cards::file_cards(&dir.join("other.md")).unwrap());
+        let (front, back, other) = (cards[0].clone(), cards[1].clone(), cards[2].clone());
+
+        let skip = Skip {
+            after_reveal: SkipAfterReveal::Allow,
+            defer: None,
+        };
+        let now = Instant::now();
+        let steps = vec![Duration::from_secs(600)];
+        let mut session =
+            ReviewSession::new(cards, "leitner", steps, false, skip, None, now).unwrap();
+        let apply = |session: &mut ReviewSession, action| session.apply(action, now).unwrap();
+        assert_eq!(session.remaining(), 3);
+
+        // remembering a card buries its reverse
+        assert_eq!(
+            apply(&mut session, Action::Continue),
+            Some(Event::CardUpdated)
+        );
+        assert_eq!(
+            apply(&mut session, Action::Continue),
+            Some(Event::CardChanged)
+        );
+        assert_eq!(session.card(), Some(&other));
+        assert_eq!(session.remaining(), 1);
+        assert_eq!(reviews(&front), 1);
+
+        // undoing brings both back and removes the review
+        assert_eq!(apply(&mut session, Action::Undo), Some(Event::CardChanged));
+        assert_eq!(session.card(), Some(&front));
+        assert_eq!(session.remaining(), 3);
+        assert_eq!(reviews(&front), 0);
+        assert_eq!(session.remembered(), 0);
+
+        // skipping moves the card to the end
+        assert_eq!(apply(&mut session, Action::Skip), Some(Event::CardChanged));
+        assert_eq!(session.card(), Some(&back));
+
+        // forgetting buries the sibling and brings the card back once the rest are done
+        assert_eq!(
+            apply(&mut session, Action::Forget),
+            Some(Event::CardChanged)
+        );
+        assert_eq!(session.forgotten(), 1);
+        assert_eq!(session.card(), Some(&other));
+        apply(&mut session, Action::Continue);
+        apply(&mut session, Action::Continue);
+        assert_eq!(session.card(), Some(&back));
+        apply(&mut session, Action::Continue);
+        assert_eq!(apply(&mut session, Action::Continue), Some(Event::Finished));
+        assert!(session.is_finished());
+        assert_eq!(reviews(&back), 2);
+        assert_eq!(reviews(&other), 1);
+        assert_eq!(reviews(&front), 0);
+
+        // nothing applies once the session is over
+        assert_eq!(apply(&mut session, Action::Undo), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn archiving_hides_the_rest_of_the_file() {
+        let (_journal, dir) = temp_dir("archive");
+        fs::write(
+            dir.join("pair.md"),
+            "---\nreverse: true\n---\nfront\n---\nback\n",
+        )
+        .unwrap();
+        fs::write(dir.join("other.md"), "question\n---\nanswer\n").unwrap();
+        let mut cards = cards::file_cards(&dir.join("other.md")).unwrap();
+        cards.extend(cards::file_cards(&dir.join("pair.md")).unwrap());
+        let (other, front, back) = (cards[0].clone(), cards[1].clone(), cards[2].clone());
+
+        let skip = Skip {
+            after_reveal: SkipAfterReveal::Allow,
+            defer: None,
+        };
+        let now = Instant::now();
+        let mut session =
+            ReviewSession::new(cards, "leitner", vec![], false, skip, None, now).unwrap();
+        session.apply(Action::Skip, now).unwrap();
+        assert_eq!(session.card(), Some(&front));
+        assert_eq!(
+            session.apply(Action::Archive, now).unwrap(),
+            Some(Event::CardChanged)
+        );
+        assert_eq!(session.card(), Some(&other));
+        assert_eq!(session.remaining(), 1);
+
+        // undoing unarchives the file and brings back both of its cards in order
+        session.apply(Action::Undo, now).unwrap();
+        assert_eq!(session.card(), Some(&front));
+        assert_eq!(session.remaining(), 3);
+        assert!(!cards::read_state(&front, "leitner").unwrap().archived);
+        session.apply(Action::Skip, now).unwrap();
+        assert_eq!(session.card(), Some(&back));
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn failed_undo_leaves_the_session_alone() {
+        let (_journal, dir) = temp_dir("failed-undo");
+        fs::write(dir.join("a.md"), "a\n---\nanswer\n").unwrap();
+        fs::write(dir.join("b.md"), "b\n---\nanswer\n").unwrap();
+        let mut cards = cards::file_cards(&dir.join("a.md")).unwrap();
+        cards.extend(cards::file_cards(&dir.join("b.md")).unwrap());
+        let (a, b) = (cards[0].clone(), cards[1].clone());
+
+        let skip = Skip {
+            after_reveal: SkipAfterReveal::Allow,
+            defer: None,
+        };
+        let now = Instant::now();
+        let steps = vec![Duration::from_secs(600)];
+        let mut session =
+            ReviewSession::new(cards, "leitner", steps, false, skip, None, now).unwrap();
+        session.apply(Action::Forget, now).unwrap();
+        assert_eq!(session.card(), Some(&b));
+
+        // the review being undone is no longer the card's last one once its file has been edited
+        fs::write(dir.join("a.md"), "a\n---\nanswer\n").unwrap();
+        match session.apply(Action::Undo, now).unwrap() {
+            Some(Event::UndoFailed(_)) => {}
+            event => panic!("unexpected {:?}", event),
+        }
+        assert_eq!(session.card(), Some(&b));
+        assert_eq!(session.forgotten(), 1);
+        assert_eq!(session.remaining(), 2);
+        assert_eq!(reviews(&a), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
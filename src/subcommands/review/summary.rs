@@ -0,0 +1,218 @@
+use chrono::Utc;
+use crossterm::style::Stylize;
+use std::error::Error;
+use std::time::Duration;
+
+use super::format_duration;
+use crate::entities::cards::{self, Card};
+use crate::entities::history::Review;
+
+/// How many of the hardest cards are listed in a summary.
+const HARDEST_CARDS: usize = 5;
+
+/// A card that was graded, along with whether it had never been reviewed before.
+pub struct Graded {
+    pub card: Card,
+    pub review: Review,
+    pub new: bool,
+}
+
+/// The statistics of a review session, or of all the reviews of a day.
+pub struct Summary {
+    /// The number of different cards that were graded.
+    reviewed: usize,
+    new: usize,
+    /// The number of cards that were remembered the first time they were graded.
+    remembered_first: usize,
+    answers: usize,
+    forgotten: usize,
+    time: Duration,
+    average_time_ms: Option<u64>,
+    /// The cards that were forgotten the most, along with how many times that happened, slowest
+    /// first among those forgotten equally often.
+    hardest: Vec<(Card, usize)>,
+    due_tomorrow: usize,
+}
+
+impl Summary {
+    /// Summarises `graded`, which took `time`, with `due_tomorrow` being the number of cards that
+    /// will be due by tomorrow.
+    pub fn new(graded: &[Graded], time: Duration, due_tomorrow: usize) -> Summary {
+        // each card is summarised by its first grade, and the number of times it was forgotten
+        let mut cards: Vec<(&Graded, usize, u64)> = Vec::new();
+        for g in graded {
+            let forgotten = if g.review.remembered { 0 } else { 1 };
+            let time_ms = g.review.time_ms.unwrap_or(0);
+            match cards.iter_mut().find(|(first, _, _)| first.card == g.card) {
+                Some((_, lapses, total_ms)) => {
+                    *lapses += forgotten;
+                    *total_ms += time_ms;
+                }
+                None => cards.push((g, forgotten, time_ms)),
+            }
+        }
+
+        let times = graded
+            .iter()
+            .filter_map(|g| g.review.time_ms)
+            .collect::<Vec<u64>>();
+        let mut hardest = cards
+            .iter()
+            .filter(|(_, lapses, _)| *lapses > 0)
+            .map(|(first, lapses, total_ms)| (first.card.clone(), *lapses, *total_ms))
+            .collect::<Vec<(Card, usize, u64)>>();
+        hardest.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)));
+
+        Summary {
+            reviewed: cards.len(),
+            new: cards.iter().filter(|(first, _, _)| first.new).count(),
+            remembered_first: cards
+                .iter()
+                .filter(|(first, _, _)| first.review.remembered)
+                .count(),
+            answers: graded.len(),
+            forgotten: graded.iter().filter(|g| !g.review.remembered).count(),
+            time,
+            average_time_ms: if times.is_empty() {
+                None
+            } else {
+                Some(times.iter().sum::<u64>() / times.len() as u64)
+            },
+            hardest: hardest
+                .into_iter()
+                .take(HARDEST_CARDS)
+                .map(|(card, lapses, _)| (card, lapses))
+                .collect(),
+            due_tomorrow,
+        }
+    }
+
+    /// Summarises the reviews recorded today for `cards`, using the answer times stored with them
+    /// as the time spent.
+    pub fn today(cards: &[Card], algorithm: &str) -> Result<Summary, Box<dyn Error>> {
+        let today = Utc::today();
+        let mut graded = Vec::new();
+        for card in cards {
+            let history = cards::read_review_history(card)?;
+            for (i, review) in history.iter().enumerate() {
+                if review.date == today {
+                    graded.push(Graded {
+                        card: card.clone(),
+                        review: *review,
+                        new: i == 0,
+                    });
+                }
+            }
+        }
+        let time = Duration::from_millis(graded.iter().filter_map(|g| g.review.time_ms).sum());
+        Ok(Summary::new(&graded, time, due_tomorrow(cards, algorithm)?))
+    }
+
+    /// Prints the summary either as text, or as a single line of JSON so that it can be appended
+    /// to a log.
+    pub fn print(&self, format: &str) {
+        match format {
+            "text" => {
+                println!(
+                    "Reviewed {} card{} ({} new, {} review{}) in {}",
+                    self.reviewed,
+                    if self.reviewed == 1 { "" } else { "s" },
+                    self.new,
+                    self.reviewed - self.new,
+                    if self.reviewed - self.new == 1 {
+                        ""
+                    } else {
+                        "s"
+                    },
+                    format_duration(self.time)
+                );
+                println!(
+                    "Retention: {}% ({} of {} remembered the first time, {} forgotten in total)",
+                    self.retention(),
+                    self.remembered_first,
+                    self.reviewed,
+                    self.forgotten
+                );
+                if let Some(average_time_ms) = self.average_time_ms {
+                    println!(
+                        "Average answer time: {:.1}s",
+                        average_time_ms as f64 / 1000.0
+                    );
+                }
+                if !self.hardest.is_empty() {
+                    println!("Hardest cards:");
+                    for (card, lapses) in &self.hardest {
+                        println!(
+                            "  {} {}",
+                            card,
+                            format!(
+                                "(forgotten {} time{})",
+                                lapses,
+                                if *lapses == 1 { "" } else { "s" }
+                            )
+                            .red()
+                        );
+                    }
+                }
+                println!(
+                    "Due tomorrow: {} card{}",
+                    self.due_tomorrow,
+                    if self.due_tomorrow == 1 { "" } else { "s" }
+                );
+            }
+            "json" => println!(
+                "{}",
+                serde_json::json!({
+                    "date": Utc::today().format(crate::entities::history::DATE_FORMAT).to_string(),
+                    "reviewed": self.reviewed,
+                    "new": self.new,
+                    "reviews": self.reviewed - self.new,
+                    "retention": self.retention(),
+                    "answers": self.answers,
+                    "forgotten": self.forgotten,
+                    "time_ms": self.time.as_millis() as u64,
+                    "average_time_ms": self.average_time_ms,
+                    "hardest": self
+                        .hardest
+                        .iter()
+                        .map(|(card, lapses)| serde_json::json!({
+                            "path": card.to_string(),
+                            "forgotten": lapses,
+                        }))
+                        .collect::<Vec<serde_json::Value>>(),
+                    "due_tomorrow": self.due_tomorrow,
+                })
+            ),
+            _ => panic!(), // Cannot occur because clap will block invalid summary format arguments
+        }
+    }
+
+    /// Returns the percentage of cards that were remembered the first time they were graded.
+    fn retention(&self) -> usize {
+        if self.reviewed == 0 {
+            0
+        } else {
+            ((self.remembered_first as f64 / self.reviewed as f64) * 100.0).round() as usize
+        }
+    }
+}
+
+/// Returns how many of `cards` will be due by tomorrow under `algorithm`, leaving out those that
+/// are archived or suspended past then.
+pub fn due_tomorrow(cards: &[Card], algorithm: &str) -> Result<usize, Box<dyn Error>> {
+    let tomorrow = Utc::today().succ();
+    let mut due = 0;
+    for card in cards {
+        let state = cards::read_state(card, algorithm)?;
+        if state.due <= tomorrow
+            && !state.archived
+            && state
+                .suspended_until
+                .map(|until| until <= tomorrow)
+                .unwrap_or(true)
+        {
+            due += 1;
+        }
+    }
+    Ok(due)
+}
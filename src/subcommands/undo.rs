@@ -0,0 +1,58 @@
+use crate::entities::journal;
+use crossterm::style::Stylize;
+use std::error::Error;
+use std::io::{self, Write};
+use std::process;
+
+pub fn undo(matches: Option<&clap::ArgMatches>) -> Result<(), Box<dyn Error>> {
+    let count = match matches.and_then(|m| m.value_of("COUNT")) {
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("invalid count `{}`", n);
+                process::exit(1);
+            }
+        },
+        None => 1,
+    };
+    let no_confirm = matches.map(|m| m.is_present("no-confirm")).unwrap_or(false);
+
+    let entries = journal::read()?;
+    let undone = &entries[entries.len().saturating_sub(count)..];
+    if undone.is_empty() {
+        eprintln!("Nothing to undo");
+        return Ok(());
+    }
+
+    if !no_confirm {
+        println!(
+            "{}",
+            "# Warning, the following will be undone...\n".bold().red()
+        );
+        for entry in undone.iter().rev() {
+            println!("{}", entry);
+        }
+
+        loop {
+            print!("Proceed? [y/N] ");
+            let _ = io::stdout().flush();
+            let mut input = String::new();
+            io::stdin().read_line(&mut input)?;
+            match input.trim().to_lowercase().as_str() {
+                "y" | "yes" => break,
+                "" | "n" | "no" => return Ok(()),
+                _ => eprintln!("{}", "Invalid input, please try again".red()),
+            }
+        }
+    }
+
+    // entries are undone newest first, and each is taken off the journal as soon as it's been
+    // reverted so that a failure part way through leaves the rest to be undone later
+    for entry in undone.iter().rev() {
+        if let Err(e) = journal::revert(entry).and_then(|_| journal::remove(entry)) {
+            eprintln!("Couldn't undo {}: {}", entry, e);
+            process::exit(1);
+        }
+    }
+    Ok(())
+}